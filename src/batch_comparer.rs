@@ -0,0 +1,104 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// 各连接方法的名称，与功能0中的代号对应
+const METHOD_NAMES: [&str; 5] = ["空格或符号", "无间隔", "键道顶功", "并击", "脚本"];
+
+/// 一次编码的条件：(词库, 连接方法代号, 文本)
+pub(crate) type Run<'a> = (&'a Path, usize, &'a Path);
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// 各文件在表中的名称：文件名互不相同时只用文件名，否则用相对于共同上级目录的路径
+fn labels<'a>(paths: impl Iterator<Item = &'a Path>) -> HashMap<&'a Path, String> {
+    let mut unique: Vec<&Path> = Vec::new();
+    for path in paths {
+        if !unique.contains(&path) {
+            unique.push(path);
+        }
+    }
+    let names: HashSet<String> = unique.iter().map(|p| file_name(p)).collect();
+    if names.len() == unique.len() {
+        return unique.into_iter().map(|p| (p, file_name(p))).collect();
+    }
+
+    let absolute: Vec<PathBuf> = unique
+        .iter()
+        .map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf()))
+        .collect();
+    let mut root = absolute[0].parent().unwrap_or(Path::new("")).to_path_buf();
+    while !absolute.iter().all(|p| p.starts_with(&root)) && root.pop() {}
+    unique
+        .into_iter()
+        .zip(&absolute)
+        .map(|(path, abs)| {
+            let relative = abs.strip_prefix(&root).unwrap_or(abs);
+            let label = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            (path, label)
+        })
+        .collect()
+}
+
+/// 从分析结果的各行中取出(指标名, 数值)。有次数和比率的指标取比率，不是`名称\t数值`格式的行跳过
+fn parse_metrics(lines: &[String]) -> Vec<(String, String)> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let (name, values) = line.split_once('\t')?;
+            let value = values.rsplit('\t').next()?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// 把各次编码的分析结果汇总成一张表，每行为一次编码，每列为一项指标。
+/// 指标按首次出现的顺序排列，某次编码没有的指标记为`-`；编码失败的行记录错误信息。
+/// 词库或文本的文件名有重复时，写出相对于共同上级目录的路径
+pub(crate) fn compare(runs: &[Run], results: &[Result<Vec<String>, String>]) -> Vec<String> {
+    let parsed: Vec<Result<Vec<(String, String)>, &String>> = results
+        .iter()
+        .map(|result| result.as_ref().map(|lines| parse_metrics(lines)))
+        .collect();
+
+    let mut names: Vec<&str> = Vec::new();
+    for metrics in parsed.iter().flatten() {
+        for (name, _) in metrics {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+    }
+
+    let dict_labels = labels(runs.iter().map(|(dict, _, _)| *dict));
+    let text_labels = labels(runs.iter().map(|(_, _, text)| *text));
+    let mut header = vec!["词库", "连接方法", "文本"];
+    header.extend(&names);
+    let mut table = vec![header.join("\t")];
+    for ((dict, method, text), metrics) in runs.iter().zip(&parsed) {
+        let mut row = vec![
+            dict_labels[dict].clone(),
+            METHOD_NAMES[*method].to_string(),
+            text_labels[text].clone(),
+        ];
+        match metrics {
+            Ok(metrics) => row.extend(names.iter().map(|name| {
+                metrics
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map_or("-".to_string(), |(_, value)| value.clone())
+            })),
+            Err(message) => row.push(format!("无法编码：{message}")),
+        }
+        table.push(row.join("\t"));
+    }
+    table
+}
@@ -0,0 +1,153 @@
+use crate::settings::Settings;
+use std::sync::{Arc, RwLock};
+
+/// 并击在编码路径中的表示：从辅助私用区A的首个码位开始，按登记顺序分配字符
+const CHORD_BASE: u32 = 0xF0000;
+
+/// 并击表：登记的并击和设置中的修饰键。由连接器持有，并传给需要解析或显示编码的地方
+#[derive(Clone)]
+pub(crate) struct Chords {
+    /// 已登记的并击。索引为字符相对于CHORD_BASE的偏移，值为升序排列、去重后的按键。
+    /// 克隆共用同一张表，使并行编码时各处登记的并击得到相同的字符
+    keys: Arc<RwLock<Vec<Vec<char>>>>,
+    /// 修饰键，如Shift，取自设置
+    modifiers: Vec<char>,
+}
+
+impl Chords {
+    pub(crate) fn new(settings: &Settings) -> Self {
+        Self {
+            keys: Arc::new(RwLock::new(Vec::new())),
+            modifiers: settings.modifiers.clone(),
+        }
+    }
+
+    /// 登记一组同时按下的键，返回代表这个并击的字符。同一组键总是得到同一个字符；只有一个键时返回该键
+    pub(crate) fn register(&self, keys: &[char]) -> char {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        if keys.len() == 1 {
+            return keys[0];
+        }
+
+        let mut chords = self.keys.write().expect("无法写入并击表");
+        let index = match chords.iter().position(|k| *k == keys) {
+            Some(index) => index,
+            None => {
+                chords.push(keys);
+                chords.len() - 1
+            }
+        };
+        char::from_u32(CHORD_BASE + index as u32).expect("并击数量超出私用区范围")
+    }
+
+    /// 获取并击包含的按键。不是并击的字符返回None
+    pub(crate) fn keys_of(&self, c: char) -> Option<Vec<char>> {
+        let index = (c as u32).checked_sub(CHORD_BASE)? as usize;
+        self.keys
+            .read()
+            .expect("无法读取并击表")
+            .get(index)
+            .cloned()
+    }
+
+    /// 已登记的所有并击，元素为(代表并击的字符, 按键)
+    pub(crate) fn registered(&self) -> Vec<(char, Vec<char>)> {
+        let chords = self.keys.read().expect("无法读取并击表");
+        chords
+            .iter()
+            .enumerate()
+            .map(|(i, keys)| {
+                let c = char::from_u32(CHORD_BASE + i as u32).expect("并击数量超出私用区范围");
+                (c, keys.clone())
+            })
+            .collect()
+    }
+
+    pub(crate) fn is_chord(&self, c: char) -> bool {
+        match (c as u32).checked_sub(CHORD_BASE) {
+            Some(index) => (index as usize) < self.keys.read().expect("无法读取并击表").len(),
+            None => false,
+        }
+    }
+
+    /// 修饰键与其后的键（或并击）合为一个并击
+    pub(crate) fn is_modifier(&self, c: char) -> bool {
+        self.modifiers.contains(&c)
+    }
+
+    /// 实际移动手指的按键：并击中的修饰键由另一只手按住，不计；单键即为自身
+    pub(crate) fn moving_keys(&self, c: char) -> Vec<char> {
+        match self.keys_of(c) {
+            Some(keys) => {
+                let moving: Vec<char> = keys
+                    .iter()
+                    .copied()
+                    .filter(|k| !self.is_modifier(*k))
+                    .collect();
+                if moving.is_empty() { keys } else { moving }
+            }
+            None => vec![c],
+        }
+    }
+
+    /// 将编码解析为按键序列，其中的并击登记到表中。花括号内的键视为同时按下，如`{jk}a`为jk并击后接a；
+    /// 修饰键与其后的键视为并击，如`↑/`。没有闭合的花括号、或括号内不足两键时，按普通字符处理
+    pub(crate) fn parse_code(&self, code: &str) -> Vec<char> {
+        let chars: Vec<char> = code.chars().collect();
+        let mut keys = Vec::with_capacity(chars.len());
+        let mut held = Vec::new(); // 按住的修饰键
+        let mut i = 0;
+        while i < chars.len() {
+            let group = if chars[i] == '{'
+                && let Some(len) = chars[i + 1..].iter().position(|c| *c == '}')
+                && len > 1
+            {
+                i += len + 2;
+                &chars[i - len - 1..i - 1]
+            } else {
+                i += 1;
+                &chars[i - 1..i]
+            };
+
+            if group.len() == 1 && self.is_modifier(group[0]) && i < chars.len() {
+                held.push(group[0]);
+            } else {
+                held.extend_from_slice(group);
+                keys.push(self.register(&held));
+                held.clear();
+            }
+        }
+        keys.append(&mut held);
+        keys
+    }
+
+    /// 将按键序列还原为文本：修饰键写在前面，其余的并击写回花括号形式
+    pub(crate) fn display(&self, keys: &[char]) -> String {
+        let mut s = String::with_capacity(keys.len());
+        for c in keys {
+            match self.keys_of(*c) {
+                Some(chord_keys) => {
+                    let (modifiers, others): (Vec<char>, Vec<char>) =
+                        chord_keys.iter().partition(|k| self.is_modifier(**k));
+                    if others.len() == 1 {
+                        s.extend(modifiers);
+                        s.push(others[0]);
+                    } else if others.is_empty() {
+                        s.push('{');
+                        s.extend(modifiers);
+                        s.push('}');
+                    } else {
+                        s.extend(modifiers);
+                        s.push('{');
+                        s.extend(others);
+                        s.push('}');
+                    }
+                }
+                None => s.push(*c),
+            }
+        }
+        s
+    }
+}
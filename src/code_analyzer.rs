@@ -1,11 +1,32 @@
+use crate::chord::Chords;
+use crate::key_map::{self, KeyMap};
+use crate::layout::{KeyInfo, Layout, THUMB};
+use crate::route_buffer::Segment;
+use crate::settings::Settings;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+/// 按键在统计表中的位置：0~4为各排，5~12为从左小指到右小指，13为拇指
+fn part_indexes(info: &KeyInfo) -> Vec<usize> {
+    let mut indexes = Vec::with_capacity(2);
+    if let Some(row) = info.row {
+        indexes.push(row);
+    }
+    match (info.hand, info.finger) {
+        (_, Some(THUMB)) => indexes.push(13),
+        (Some(0), Some(finger)) => indexes.push(5 + finger),
+        (Some(1), Some(finger)) => indexes.push(12 - finger),
+        _ => {}
+    }
+    indexes
+}
+
 pub(crate) fn analyze(
-    layout: Vec<String>,
+    layout: &Layout,
     text_len: usize,
     route: Vec<char>,
+    key_map: &KeyMap,
+    chords: &Chords,
     time: f64,
 ) -> Vec<String> {
     // 简单分析
@@ -14,10 +35,10 @@ pub(crate) fn analyze(
     let time_per_key = time / route.len() as f64;
 
     // 简单返回
-    if layout.len() != 14 {
+    if layout.is_empty() {
         println!("键盘布局配置错误，将只进行简单分析。");
         return vec![
-            route.iter().collect(),
+            chords.display(&route),
             "---以上为最优编码路径，以下为简单分析结果---".to_string(),
             format!("字数\t{}", text_len),
             format!("码数\t{}", route.len()),
@@ -28,6 +49,10 @@ pub(crate) fn analyze(
         ];
     }
 
+    // 完整分析按实际按下的物理按键进行
+    let route_line = chords.display(&route);
+    let route = key_map::map_keys(&route, key_map, chords);
+
     // 完整分析的变量
     let mut parts_count = Vec::with_capacity(14); // 每组码的计数
     for _ in 0..14 {
@@ -44,57 +69,46 @@ pub(crate) fn analyze(
     let turns_count = AtomicUsize::new(0); // 左右左 + 右左右的次数
 
     // 完整分析的方法
-    let double_contains = |c1: char, c2: char, s1: &str, s2: &str| {
-        (s1.contains(c1) && s2.contains(c2)) || (s1.contains(c2) && s2.contains(c1))
-    };
-
-    let same_finger = |c1: char, c2: char| {
-        double_contains(c1, c2, &layout[5], &layout[5])
-            || double_contains(c1, c2, &layout[6], &layout[6])
-            || double_contains(c1, c2, &layout[7], &layout[7])
-            || double_contains(c1, c2, &layout[8], &layout[8])
-            || double_contains(c1, c2, &layout[9], &layout[9])
-            || double_contains(c1, c2, &layout[10], &layout[10])
-            || double_contains(c1, c2, &layout[11], &layout[11])
-            || double_contains(c1, c2, &layout[12], &layout[12])
-    };
-
-    let s_leap = |c1: char, c2: char| {
-        double_contains(c1, c2, &layout[0], &layout[1])
-            || double_contains(c1, c2, &layout[1], &layout[2])
-            || double_contains(c1, c2, &layout[2], &layout[3])
+    // 同指（不含拇指）异键时，返回跨越的排数（底排不计）
+    let leap_rows = |c1: char, c2: char| {
+        let (i1, i2) = (layout.get(c1)?, layout.get(c2)?);
+        if !i1.same_finger(i2) || i1.is_thumb() {
+            return None;
+        }
+        match (i1.row, i2.row) {
+            (Some(r1), Some(r2)) if r1 < 4 && r2 < 4 => Some(r1.abs_diff(r2)),
+            _ => None,
+        }
     };
 
-    let m_leap = |c1: char, c2: char| {
-        double_contains(c1, c2, &layout[0], &layout[2])
-            || double_contains(c1, c2, &layout[1], &layout[3])
+    // 非拇指键所属的手
+    let hand_of = |c: char| {
+        layout
+            .get(c)
+            .filter(|info| !info.is_thumb())
+            .and_then(|info| info.hand)
     };
 
-    let l_leap = |c1: char, c2: char| double_contains(c1, c2, &layout[0], &layout[3]);
-
-    let left_keys: HashSet<char> = layout[5]
-        .chars()
-        .chain(layout[6].chars())
-        .chain(layout[7].chars())
-        .chain(layout[8].chars())
-        .collect();
-
-    let right_keys: HashSet<char> = layout[9]
-        .chars()
-        .chain(layout[10].chars())
-        .chain(layout[11].chars())
-        .chain(layout[12].chars())
-        .collect();
-
     let turns = |c1: char, c2: char, c3: char| {
-        left_keys.contains(&c1) && right_keys.contains(&c2) && left_keys.contains(&c3)
-            || right_keys.contains(&c1) && left_keys.contains(&c2) && right_keys.contains(&c3)
+        let (h1, h2, h3) = (hand_of(c1), hand_of(c2), hand_of(c3));
+        h1.is_some() && h2.is_some() && h1 != h2 && h1 == h3
     };
 
     let count_1_char = |c: char| {
-        for i in 0..14 {
-            if layout[i].contains(c) {
-                parts_count[i].fetch_add(1, Ordering::Relaxed);
+        // 并击按其中的每个键分别计数
+        let keys = chords.keys_of(c).unwrap_or(vec![c]);
+        let on_left = keys
+            .iter()
+            .any(|k| !chords.is_modifier(*k) && hand_of(*k) == Some(0));
+        for key in keys {
+            let indexes = layout.get(key).map(part_indexes).unwrap_or_default();
+            for i in &indexes {
+                parts_count[*i].fetch_add(1, Ordering::Relaxed);
+            }
+            // 布局中没有指定手指的修饰键，由另一只手的小指按住
+            if !indexes.iter().any(|i| *i > 4) && chords.is_modifier(key) {
+                let pinky = if on_left { 12 } else { 5 };
+                parts_count[pinky].fetch_add(1, Ordering::Relaxed);
             }
         }
     };
@@ -102,14 +116,13 @@ pub(crate) fn analyze(
     let count_2_chars = |c1: char, c2: char| {
         if c1 == c2 {
             double_count.fetch_add(1, Ordering::Relaxed);
-        } else if same_finger(c1, c2) {
-            if s_leap(c1, c2) {
-                s_leap_count.fetch_add(1, Ordering::Relaxed);
-            } else if m_leap(c1, c2) {
-                m_leap_count.fetch_add(1, Ordering::Relaxed);
-            } else if l_leap(c1, c2) {
-                l_leap_count.fetch_add(1, Ordering::Relaxed);
-            }
+        } else {
+            match leap_rows(c1, c2) {
+                Some(1) => s_leap_count.fetch_add(1, Ordering::Relaxed),
+                Some(2) => m_leap_count.fetch_add(1, Ordering::Relaxed),
+                Some(3) => l_leap_count.fetch_add(1, Ordering::Relaxed),
+                _ => 0,
+            };
         }
     };
 
@@ -177,12 +190,12 @@ pub(crate) fn analyze(
     let gen_report = |name: &str, involved_len: usize, count: usize| {
         format!(
             "{name}\t{count}\t{:.3}%",
-            100.0 * count as f64 / (route.len() - involved_len + 1) as f64
+            100.0 * count as f64 / (route.len() + 1).saturating_sub(involved_len).max(1) as f64
         )
     };
 
     vec![
-        route.iter().collect(),
+        route_line,
         "---以上为最优编码路径，以下为完整分析结果---".to_string(),
         format!("字数\t{}", text_len),
         format!("码数\t{}", route.len()),
@@ -217,3 +230,66 @@ pub(crate) fn analyze(
         gen_report("左右互击", 3, turns_count.load(Ordering::Relaxed)),
     ]
 }
+
+/// 报告编码所用的选重、翻页次数，以及编码时最小化的目标值和各项权重
+pub(crate) fn analyze_objective(
+    settings: &Settings,
+    time: f64,
+    key_count: usize,
+    segments: &[Segment],
+) -> Vec<String> {
+    let selections: usize = segments.iter().map(|s| s.picks.0).sum();
+    let pages: usize = segments.iter().map(|s| s.picks.1).sum();
+    let score = settings.obj_time_weight * time
+        + settings.obj_key_weight * key_count as f64
+        + settings.obj_selection_weight * selections as f64
+        + settings.obj_page_weight * pages as f64;
+    vec![
+        format!("选重次数\t{selections}"),
+        format!("翻页次数\t{pages}"),
+        format!("目标值\t{score:.1}"),
+        format!(
+            "目标权重\t当量\t{}\t码数\t{}\t选重\t{}\t翻页\t{}",
+            settings.obj_time_weight,
+            settings.obj_key_weight,
+            settings.obj_selection_weight,
+            settings.obj_page_weight
+        ),
+    ]
+}
+
+/// 分别统计直接键入的西文和其余部分的字数、码数、当量。各段的码数和当量按连接后的增量计
+pub(crate) fn analyze_latin(route_len: usize, time: f64, segments: &[Segment]) -> Vec<String> {
+    let (mut chars, mut keys, mut latin_time, mut runs) = (0, 0, 0.0, 0);
+    let mut prev_time = 0.0;
+    for (i, segment) in segments.iter().enumerate() {
+        let key_end = segments.get(i + 1).map_or(route_len, |s| s.key_start);
+        if segment.latin {
+            chars += segment.len;
+            keys += key_end - segment.key_start;
+            latin_time += segment.time - prev_time;
+            if i == 0
+                || !segments[i - 1].latin
+                || segments[i - 1].start + segments[i - 1].len != segment.start
+            {
+                runs += 1;
+            }
+        }
+        prev_time = segment.time;
+    }
+    let text_len: usize = segments.iter().map(|s| s.len).sum();
+    let (other_chars, other_keys, other_time) =
+        (text_len - chars, route_len - keys, time - latin_time);
+    let per_char = |t: f64, n: usize| t / n.max(1) as f64;
+    vec![
+        format!("西文片段数\t{runs}"),
+        format!(
+            "西文部分\t字数\t{chars}\t码数\t{keys}\t当量\t{latin_time:.1}\t字均当量\t{:.4}",
+            per_char(latin_time, chars)
+        ),
+        format!(
+            "中文部分\t字数\t{other_chars}\t码数\t{other_keys}\t当量\t{other_time:.1}\t字均当量\t{:.4}",
+            per_char(other_time, other_chars)
+        ),
+    ]
+}
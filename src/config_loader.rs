@@ -1,54 +1,158 @@
-use std::collections::{HashMap, HashSet};
+use crate::chord::Chords;
+use crate::connector_script::ConnectorScript;
+use crate::file_decoder::decode;
+use crate::layout::Layout;
+use crate::settings::Settings;
+use crate::time_map::TimeMap;
+use std::collections::HashSet;
 use std::env::current_exe;
-use std::fs::{File, read_to_string};
-use std::io::{BufRead, BufReader};
+use std::fs::read;
 use std::path::PathBuf;
 
-fn get_config_path(name: &str) -> Result<PathBuf, &'static str> {
+pub(crate) fn get_config_path(name: &str) -> Result<PathBuf, &'static str> {
     let exe_path = current_exe().map_err(|_| "无法获取可执行文件路径")?;
     let config_dir = exe_path.parent().ok_or("无法获取程序目录")?;
     Ok(config_dir.join("config").join(name))
 }
 
-pub(crate) fn load_layout() -> Result<Vec<String>, &'static str> {
+pub(crate) fn load_settings() -> Result<Settings, &'static str> {
+    println!("加载设置...");
+    let mut settings = Settings::default();
+    let settings_path = get_config_path("settings.txt")?;
+    if !settings_path.exists() {
+        println!("没有设置文件，使用默认设置。");
+        return Ok(settings);
+    }
+
+    let bytes = read(&settings_path).map_err(|_| "无法读取设置文件")?;
+    let content = decode(&bytes, "自动")?; // 设置文件本身总是自动识别编码
+    for line in content.lines() {
+        let item = line.split('#').next().expect("无法解析设置文件中的注释");
+        if item.trim().is_empty() {
+            continue;
+        }
+        match item.split_once('\t') {
+            Some((name, value)) => {
+                if let Err(message) =
+                    settings.set(name.trim(), value.trim_end_matches(['\r', '\n']))
+                {
+                    println!("无法应用此行的设置：{line}。错误信息：{message}");
+                }
+            }
+            None => println!("设置文件中有格式错误的行：{line}"),
+        }
+    }
+    println!("加载完成。");
+    Ok(settings)
+}
+
+/// 优先加载新版布局文件keyboard.txt；没有则导入旧版的14行布局文件layout.txt
+pub(crate) fn load_layout(settings: &Settings) -> Result<Layout, &'static str> {
     println!("加载键盘布局配置...");
+    let keyboard_path = get_config_path("keyboard.txt")?;
+    if !keyboard_path.exists() {
+        return load_legacy_layout(settings);
+    }
+
+    let bytes = read(&keyboard_path).map_err(|_| "无法读取键盘布局文件")?;
+    let content = decode(&bytes, &settings.config_encoding)?;
+    let mut layout = Layout::new();
+    for line in content.lines() {
+        // 以#号开头的行为注释，但#号本身也可以是按键
+        if line.trim().is_empty() || (line.starts_with('#') && !line.starts_with("#\t")) {
+            continue;
+        }
+        if let Err(message) = layout.add_line(line) {
+            println!("键盘布局文件中有格式错误的行：{line}。错误信息：{message}");
+        }
+    }
+    println!("加载完成。共{}个按键。", layout.len());
+    Ok(layout)
+}
+
+fn load_legacy_layout(settings: &Settings) -> Result<Layout, &'static str> {
+    println!("没有新版布局文件，导入旧版布局文件...");
     let layout_path = get_config_path("layout.txt")?;
-    let content = read_to_string(&layout_path).map_err(|_| "无法读取键盘布局文件")?;
+    let bytes = read(&layout_path).map_err(|_| "无法读取键盘布局文件")?;
+    let content = decode(&bytes, &settings.config_encoding)?;
     let layout_lines: Vec<String> = content.lines().map(|line| line.to_string()).collect();
     println!("加载完成。应为14行，实际为{}行。", layout_lines.len());
-    Ok(layout_lines)
+    if layout_lines.len() != 14 {
+        return Ok(Layout::new()); // 空布局只进行简单分析
+    }
+    Ok(Layout::from_lines(&layout_lines))
 }
 
-pub(crate) fn load_punct_items() -> Result<HashSet<(String, String, usize)>, &'static str> {
+pub(crate) fn load_punct_items(
+    settings: &Settings,
+) -> Result<HashSet<(String, String, usize)>, &'static str> {
     println!("加载标点符号配置...");
     let punct_path = get_config_path("punct_dict.txt")?;
-    let punct_file = File::open(&punct_path).map_err(|_| "无法打开标点符号文件")?;
-    let items = crate::dict_loader::read_rime_file(punct_file, 32)?;
+    let bytes = read(&punct_path).map_err(|_| "无法读取标点符号文件")?;
+    let items =
+        crate::dict_loader::parse_rime_items(&decode(&bytes, &settings.config_encoding)?, 32);
     println!("加载完成。默认为30项，实际为{}项。", items.len());
     Ok(items)
 }
 
-pub(crate) fn load_time_map() -> Result<HashMap<(char, char), f64>, &'static str> {
+pub(crate) fn load_connector_script(
+    settings: &Settings,
+    chords: &Chords,
+) -> Result<ConnectorScript, String> {
+    println!("加载连接脚本...");
+    let script_path = get_config_path("connector.rhai")?;
+    let script = ConnectorScript::load(&script_path, &settings.config_encoding, chords)?;
+    println!("加载完成。");
+    Ok(script)
+}
+
+/// 击键当量文件的原始内容，用于在其后追加生成的当量
+pub(crate) fn load_time_map_lines(settings: &Settings) -> Result<Vec<String>, &'static str> {
+    let time_map_path = get_config_path("time_map.txt")?;
+    let bytes = read(&time_map_path).map_err(|_| "无法读取击键当量文件")?;
+    let content = decode(&bytes, &settings.config_encoding)?;
+    Ok(content.lines().map(|line| line.to_string()).collect())
+}
+
+/// 加载击键当量表，其中的并击登记到并击表中
+pub(crate) fn load_time_map(settings: &Settings, chords: &Chords) -> Result<TimeMap, &'static str> {
     println!("加载击键当量配置...");
     let time_map_path = get_config_path("time_map.txt")?;
-    let time_map_file = File::open(&time_map_path).map_err(|_| "无法打开击键当量文件")?;
+    let bytes = read(&time_map_path).map_err(|_| "无法读取击键当量文件")?;
+    let content = decode(&bytes, &settings.config_encoding)?;
 
-    let mut time_map = HashMap::with_capacity(4096);
-    for line in BufReader::new(time_map_file).lines() {
-        let line = line.map_err(|_| "无法读取击键当量文件中的一行")?;
+    let mut time_map = TimeMap::new();
+    for line in content.lines() {
         let parts: Vec<&str> = line.split('\t').collect();
-        let keys: Vec<char> = parts[0].chars().collect();
-        if parts.len() != 2 || keys.len() != 2 {
+        let keys = chords.parse_code(parts[0]);
+        let is_chord_cost = keys.len() == 1 && chords.is_chord(keys[0]);
+        if parts.len() != 2 || !(keys.len() == 2 || keys.len() == 3 || is_chord_cost) {
             println!("击键当量文件中有格式错误的行：{}", line);
             continue;
         }
 
         match parts[1].parse() {
             Err(message) => println!("无法解析此行的击键当量：{line}。错误信息：{message}"),
-            Ok(time_cost) => match time_map.get(&(keys[0], keys[1])) {
+            Ok(time_cost) if is_chord_cost => match time_map.chords.get(&keys[0]) {
+                Some(_) => println!("击键当量文件中有重复的并击：{}", parts[0]),
+                None => {
+                    time_map.chords.insert(keys[0], time_cost);
+                }
+            },
+            Ok(time_cost) if keys.len() == 3 => {
+                match time_map.triples.get(&(keys[0], keys[1], keys[2])) {
+                    Some(_) => println!("击键当量文件中有重复的三键组合：{}", parts[0]),
+                    None => {
+                        time_map
+                            .triples
+                            .insert((keys[0], keys[1], keys[2]), time_cost);
+                    }
+                }
+            }
+            Ok(time_cost) => match time_map.pairs.get(&(keys[0], keys[1])) {
                 Some(_) => println!("击键当量文件中有重复的键：{}", parts[0]),
                 None => {
-                    time_map.insert((keys[0], keys[1]), time_cost);
+                    time_map.pairs.insert((keys[0], keys[1]), time_cost);
                 }
             },
         }
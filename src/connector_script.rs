@@ -0,0 +1,78 @@
+use crate::chord::Chords;
+use crate::file_decoder::decode;
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+use std::path::Path;
+use std::sync::Arc;
+
+/// 传给脚本的上文末尾按键数
+pub(crate) const TAIL_LEN: usize = 4;
+/// 每次调用脚本至多执行的操作数，避免死循环的脚本卡住编码
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// 用Rhai脚本编写的连接规则。脚本须定义`connect(tail, code, tail_time, code_time)`：
+/// tail为上文末尾至多4个按键，code为新编码，并击均写作`{...}`；
+/// 返回连接后替换这两部分的按键字符串，或`#{keys: 按键字符串, cost: 额外当量}`
+#[derive(Clone)]
+pub(crate) struct ConnectorScript {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+}
+
+impl ConnectorScript {
+    pub(crate) fn load(path: &Path, encoding: &str, chords: &Chords) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        let source = decode(&bytes, encoding)?;
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        let script = Self {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+        };
+        // 试运行一次，尽早发现脚本中的错误
+        script.join(chords, &['a', 'b'], &['c', 'd'], 1.0, 1.0)?;
+        Ok(script)
+    }
+
+    /// 调用脚本，返回连接后的按键和额外当量。按键中的并击登记到并击表中
+    pub(crate) fn join(
+        &self,
+        chords: &Chords,
+        tail: &[char],
+        code: &[char],
+        tail_time: f64,
+        code_time: f64,
+    ) -> Result<(Vec<char>, f64), String> {
+        let args = (
+            chords.display(tail),
+            chords.display(code),
+            tail_time,
+            code_time,
+        );
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, "connect", args)
+            .map_err(|e| e.to_string())?;
+
+        if result.is_string() {
+            let keys = result.into_string()?;
+            return Ok((chords.parse_code(&keys), 0.0));
+        }
+        let map = result
+            .try_cast::<Map>()
+            .ok_or("connect的返回值应为字符串或#{keys, cost}")?;
+        let keys = map
+            .get("keys")
+            .cloned()
+            .ok_or("返回值中缺少keys")?
+            .into_string()?;
+        let cost = match map.get("cost") {
+            None => 0.0,
+            Some(cost) => cost
+                .as_float()
+                .or_else(|_| cost.as_int().map(|i| i as f64))
+                .map_err(|_| "cost应为数值".to_string())?,
+        };
+        Ok((chords.parse_code(&keys), cost))
+    }
+}
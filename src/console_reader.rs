@@ -1,5 +1,13 @@
-use crate::dict_loader::load_dict;
+use crate::chord::Chords;
+use crate::config_loader::load_connector_script;
+use crate::connector_script::ConnectorScript;
+use crate::corpus_encoder::{collect_files, load_weights};
+use crate::dict_loader::{Dict, SortedItems, load_dict, load_sorted_items};
+use crate::key_map::{KeyMap, load_key_map};
+use crate::layout::Layout;
 use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+use crate::time_map::TimeMap;
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
@@ -13,23 +21,108 @@ pub(crate) fn read_line() -> String {
     }
 }
 
-pub(crate) fn get_connector(time_map: HashMap<(char, char), f64>) -> RouteConnector {
+pub(crate) fn get_mode() -> usize {
+    println!("请输入功能代号：");
+    println!("0: 计算最小当量编码; 1: 生成击键当量表; 2: 校验击键当量表; 3: 拟合击键当量表");
+    println!("4: 优化键盘布局; 5: 优化简码分配; 6: 列出候选路径; 7: 模拟实际打字");
+    println!("8: 编码语料库; 9: 批量对比词库和连接方法");
+    loop {
+        match read_line().parse() {
+            Ok(code) if code < 10 => return code,
+            _ => println!("无效代号。请重新输入。"),
+        }
+    }
+}
+
+pub(crate) fn get_connector(
+    time_map: TimeMap,
+    chords: Chords,
+    settings: Settings,
+    layout: Layout,
+    key_map: KeyMap,
+) -> RouteConnector {
     println!("请输入连接方法代号：");
-    println!("0: 空格或符号; 1: 无间隔; 2: 键道顶功");
+    println!("0: 空格或符号; 1: 无间隔; 2: 键道顶功; 3: 并击; 4: 脚本(config/connector.rhai)");
     loop {
-        if let Ok(code) = read_line().parse() {
-            if code < 3 {
-                return RouteConnector::new(time_map, code);
+        match read_line().parse() {
+            Ok(code) if code < 4 => {
+                return RouteConnector::new(
+                    time_map, chords, settings, layout, key_map, code, None,
+                );
             }
+            Ok(4) => match load_connector_script(&settings, &chords) {
+                Ok(script) => {
+                    let script = Some(script);
+                    return RouteConnector::new(
+                        time_map, chords, settings, layout, key_map, 4, script,
+                    );
+                }
+                Err(message) => {
+                    println!("无法加载连接脚本。错误信息：{message}。请修改脚本或选择其他方法。");
+                    continue;
+                }
+            },
+            _ => {}
         }
         println!("无效代号。请重新输入。")
     }
 }
 
+/// 读取多个连接方法代号。包含脚本时加载脚本
+pub(crate) fn get_methods(
+    settings: &Settings,
+    chords: &Chords,
+) -> (Vec<usize>, Option<ConnectorScript>) {
+    println!("请输入以空格分隔的多个连接方法代号（如0 1 3）：");
+    println!("0: 空格或符号; 1: 无间隔; 2: 键道顶功; 3: 并击; 4: 脚本(config/connector.rhai)");
+    loop {
+        let codes: Option<Vec<usize>> = read_line()
+            .split_whitespace()
+            .map(|code| code.parse().ok().filter(|code| *code < 5))
+            .collect();
+        let mut codes = match codes {
+            Some(codes) if !codes.is_empty() => codes,
+            _ => {
+                println!("无效代号。请重新输入。");
+                continue;
+            }
+        };
+        let mut seen = HashSet::new();
+        codes.retain(|code| seen.insert(*code)); // 去掉重复的代号，保持输入顺序
+        if !codes.contains(&4) {
+            return (codes, None);
+        }
+        match load_connector_script(settings, chords) {
+            Ok(script) => return (codes, Some(script)),
+            Err(message) => {
+                println!("无法加载连接脚本。错误信息：{message}。请修改脚本或选择其他方法。")
+            }
+        }
+    }
+}
+
+pub(crate) fn get_key_map(settings: &Settings) -> KeyMap {
+    println!("请输入按键映射文件路径（直接回车则不映射）：");
+    loop {
+        let line = read_line();
+        if line.is_empty() {
+            return KeyMap::new();
+        }
+        let path = PathBuf::from(line);
+        match path.exists() {
+            true => match load_key_map(&path, &settings.config_encoding) {
+                Ok(key_map) => return key_map,
+                Err(message) => println!("无法加载按键映射。错误信息：{message}。请重新输入。"),
+            },
+            false => println!("文件不存在。请重新输入。"),
+        }
+    }
+}
+
 pub(crate) fn get_dict(
     punct_items: HashSet<(String, String, usize)>,
     connector: RouteConnector,
-) -> (HashMap<char, Vec<(Vec<char>, Vec<char>, f64)>>, usize) {
+) -> (Dict, usize) {
     println!("请输入词库文件路径：");
     loop {
         let path = PathBuf::from(read_line());
@@ -43,6 +136,41 @@ pub(crate) fn get_dict(
     }
 }
 
+/// 逐行读取多个文件路径，空行结束
+pub(crate) fn get_paths(name: &str) -> Vec<PathBuf> {
+    println!("请逐行输入{name}文件路径，输入空行结束：");
+    let mut paths = Vec::new();
+    loop {
+        let line = read_line();
+        if line.is_empty() {
+            return paths;
+        }
+        let path = PathBuf::from(line);
+        match path.exists() {
+            true => paths.push(path),
+            false => println!("文件不存在。请重新输入。"),
+        }
+    }
+}
+
+/// 读取词库，返回排序后的词库条目和标点符号条目，供反复生成词库
+pub(crate) fn get_dict_items(
+    punct_items: &HashSet<(String, String, usize)>,
+    settings: &Settings,
+) -> (SortedItems, SortedItems) {
+    println!("请输入词库文件路径：");
+    loop {
+        let path = PathBuf::from(read_line());
+        match path.exists() {
+            true => match load_sorted_items(&path, punct_items, &settings.dict_encoding) {
+                Ok(items) => return items,
+                Err(message) => println!("无法加载词库。错误信息：{message}。请重新输入。"),
+            },
+            false => println!("文件不存在。请重新输入。"),
+        }
+    }
+}
+
 pub(crate) fn get_text_path() -> PathBuf {
     println!("请输入待编码文本文件路径：");
     loop {
@@ -54,8 +182,70 @@ pub(crate) fn get_text_path() -> PathBuf {
     }
 }
 
+/// 读取语料库的目录或通配符路径，返回(根路径, 文件列表)
+pub(crate) fn get_corpus_files() -> (PathBuf, Vec<PathBuf>) {
+    println!("请输入语料库目录（编码其中所有txt文件）或通配符路径（如corpus/**/*.txt）：");
+    loop {
+        match collect_files(&read_line()) {
+            Ok(corpus) => return corpus,
+            Err(message) => println!("无法读取语料库。错误信息：{message}。请重新输入。"),
+        }
+    }
+}
+
+pub(crate) fn get_corpus_weights(settings: &Settings) -> HashMap<String, f64> {
+    println!("请输入各文件权重的文件路径（直接回车则权重均为1）：");
+    loop {
+        let line = read_line();
+        if line.is_empty() {
+            return HashMap::new();
+        }
+        match load_weights(&PathBuf::from(line), &settings.config_encoding) {
+            Ok(weights) => return weights,
+            Err(message) => println!("无法加载权重。错误信息：{message}。请重新输入。"),
+        }
+    }
+}
+
+pub(crate) fn need_to_fill_gaps() -> bool {
+    println!("是否只填补现有击键当量表中没有的组合？");
+    println!("随便输入一个数字以确认；输入其他则生成完整的表...");
+    read_line().parse::<f64>().is_ok()
+}
+
 pub(crate) fn need_to_report_unknown_keys(count: usize) -> bool {
     println!("是否需要输出这{count}个找不到当量的按键组合？");
     println!("随便输入一个数字以确认；输入其他则取消...");
     read_line().parse::<f64>().is_ok()
 }
+
+pub(crate) fn need_to_report_usage() -> bool {
+    println!("是否需要输出词库使用报告（各词条用到的次数，以及没用到的词条）？");
+    println!("随便输入一个数字以确认；输入其他则取消...");
+    read_line().parse::<f64>().is_ok()
+}
+
+/// 读取要逐段输出编码的文本范围，返回从0开始的字序号范围
+pub(crate) fn get_annotation_range(text_len: usize) -> Option<(usize, usize)> {
+    println!("是否需要逐段输出文本和所用的按键？");
+    println!(
+        "输入0输出全文；输入以空格分隔的起止字序号（从1开始，如1 500）输出这一范围；直接回车则取消..."
+    );
+    loop {
+        let input = read_line();
+        match input.as_str() {
+            "" => return None,
+            "0" => return Some((0, text_len)),
+            _ => {}
+        }
+        let range = input
+            .split_once(' ')
+            .map(|(start, end)| (start.trim().parse::<usize>(), end.trim().parse::<usize>()));
+        match range {
+            Some((Ok(start), Ok(end))) if start >= 1 && start <= end && start <= text_len => {
+                return Some((start - 1, end.min(text_len)));
+            }
+            _ => println!("无效范围。请重新输入。"),
+        }
+    }
+}
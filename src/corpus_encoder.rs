@@ -0,0 +1,201 @@
+use crate::dict_loader::Dict;
+use crate::file_decoder::decode;
+use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+use crate::text_encoder;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 一个文件的编码结果：(字数, 码数, 当量)
+type FileResult = (usize, usize, f64);
+
+/// 递归收集目录下的所有txt文件
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), &'static str> {
+    let entries = std::fs::read_dir(dir).map_err(|_| "无法读取语料库目录")?;
+    for entry in entries {
+        let path = entry.map_err(|_| "无法读取语料库目录")?.path();
+        if path.is_dir() {
+            walk_dir(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 按目录、通配符路径或单个文件收集待编码的文件，按路径排序。
+/// 返回(根路径, 文件列表)：根路径为目录本身、通配符之前的目录或单个文件，报告保存在其旁边
+pub(crate) fn collect_files(input: &str) -> Result<(PathBuf, Vec<PathBuf>), &'static str> {
+    let (root, mut files) = if input.contains(['*', '?', '[']) {
+        let paths = glob::glob(input).map_err(|_| "通配符路径格式有误")?;
+        let files: Vec<PathBuf> = paths
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .collect();
+        // 根路径为第一个含通配符的部分之前的目录
+        let root: PathBuf = Path::new(input)
+            .components()
+            .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+            .collect();
+        let root = match root.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => root,
+        };
+        (root, files)
+    } else {
+        let path = PathBuf::from(input);
+        if path.is_dir() {
+            let mut files = Vec::new();
+            walk_dir(&path, &mut files)?;
+            (path, files)
+        } else if path.is_file() {
+            (path.clone(), vec![path])
+        } else {
+            return Err("路径不存在");
+        }
+    };
+    if files.is_empty() {
+        return Err("没有找到待编码的文件");
+    }
+    files.sort();
+    let root = root
+        .canonicalize()
+        .map_err(|_| "无法获取语料库的绝对路径")?;
+    let files = files
+        .into_iter()
+        .map(|f| f.canonicalize().map_err(|_| "无法获取语料库文件的绝对路径"))
+        .collect::<Result<_, _>>()?;
+    Ok((root, files))
+}
+
+/// 文件相对于根路径的路径，以`/`分隔，用于报告和权重文件。根路径即文件本身时为文件名
+pub(crate) fn relative_name(root: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(root).unwrap_or(file);
+    match relative.as_os_str().is_empty() {
+        true => file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        false => relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    }
+}
+
+/// 读取各文件的权重。每行为`相对路径\t权重`，`#`开头的行为注释；没有列出的文件权重为1
+pub(crate) fn load_weights(
+    path: &Path,
+    encoding: &str,
+) -> Result<HashMap<String, f64>, &'static str> {
+    let bytes = read(path).map_err(|_| "无法读取权重文件")?;
+    let content = decode(&bytes, encoding)?;
+    let mut weights = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, weight) = line.rsplit_once('\t').ok_or("权重文件格式有误")?;
+        let weight: f64 = weight
+            .trim()
+            .parse()
+            .map_err(|_| "权重文件中有无效的权重")?;
+        if !weight.is_finite() || weight < 0.0 {
+            return Err("权重不能为负数");
+        }
+        weights.insert(name.trim().replace('\\', "/"), weight);
+    }
+    Ok(weights)
+}
+
+/// 并行编码各文件，返回每个文件的结果（与文件列表一一对应）和合并了找不到当量的按键组合、脚本错误的连接器
+pub(crate) fn encode(
+    files: &[PathBuf],
+    dict: &Dict,
+    connector: &RouteConnector,
+    settings: &Settings,
+    buffer_size: usize,
+) -> (Vec<Result<FileResult, &'static str>>, RouteConnector) {
+    let done = AtomicUsize::new(0);
+    let encoded: Vec<_> = files
+        .par_iter()
+        .map(|path| {
+            let result = text_encoder::read_text(path, settings).and_then(|(text, _)| {
+                let encoded =
+                    text_encoder::encode(&text, dict, connector.clone(), buffer_size, false)?;
+                Ok((text.len(), encoded))
+            });
+            let count = done.fetch_add(1, Ordering::Relaxed) + 1;
+            print!("\r已完成{count}/{}个文件。", files.len());
+            result
+        })
+        .collect();
+    println!();
+
+    let mut merged = connector.clone();
+    let results = encoded
+        .into_iter()
+        .map(|result| {
+            result.map(|(text_len, encoded)| {
+                merged.merge_records(&encoded.connector);
+                (text_len, encoded.route.len(), encoded.time)
+            })
+        })
+        .collect();
+    (results, merged)
+}
+
+/// 汇总各文件的结果，返回报告的各行。总数按权重累加，平均值为加权平均
+pub(crate) fn report(
+    root: &Path,
+    files: &[PathBuf],
+    results: &[Result<FileResult, &'static str>],
+    weights: &HashMap<String, f64>,
+) -> Vec<String> {
+    let (mut chars, mut keys, mut time) = (0.0, 0.0, 0.0);
+    let mut lines = vec!["文件\t权重\t字数\t码数\t当量\t字均当量".to_string()];
+    let mut failures = Vec::new();
+    for (path, result) in files.iter().zip(results) {
+        let name = relative_name(root, path);
+        match result {
+            Ok((text_len, key_count, file_time)) => {
+                let weight = weights.get(&name).copied().unwrap_or(1.0);
+                chars += weight * *text_len as f64;
+                keys += weight * *key_count as f64;
+                time += weight * file_time;
+                lines.push(format!(
+                    "{name}\t{weight}\t{text_len}\t{key_count}\t{file_time:.1}\t{:.4}",
+                    file_time / (*text_len).max(1) as f64
+                ));
+            }
+            Err(message) => failures.push(format!("{name}\t{message}")),
+        }
+    }
+
+    let mut report = vec![
+        format!("文件数\t{}", files.len()),
+        format!("成功编码\t{}", files.len() - failures.len()),
+        format!("加权字数\t{chars:.1}"),
+        format!("加权码数\t{keys:.1}"),
+        format!("加权当量\t{time:.1}"),
+        format!("字均当量\t{:.4}", time / chars.max(f64::MIN_POSITIVE)),
+        format!("码均当量\t{:.4}", time / keys.max(f64::MIN_POSITIVE)),
+        format!("字均码长\t{:.4}", keys / chars.max(f64::MIN_POSITIVE)),
+        "---以下为各文件的结果---".to_string(),
+    ];
+    report.extend(lines);
+    if !failures.is_empty() {
+        report.push("---以下为无法编码的文件：文件、错误信息---".to_string());
+        report.extend(failures);
+    }
+    report
+}
@@ -1,17 +1,23 @@
+use crate::file_decoder::decode;
+use crate::route_buffer::compare;
 use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 
-/// 将Rime格式词库文件中的每一行(词组, 编码, 优先级)解析并返回一个HashSet
-pub(crate) fn read_rime_file(
-    file: File,
-    capacity: usize,
-) -> Result<HashSet<(String, String, usize)>, &'static str> {
+/// 编码中生成的选重键和翻页键的个数(选重, 翻页)
+pub(crate) type Picks = (usize, usize);
+
+/// 按首字符分组的词库。子词库中的元素为(词组, 编码, 击键当量, 选重和翻页次数)
+pub(crate) type Dict = HashMap<char, Vec<(Vec<char>, Vec<char>, f64, Picks)>>;
+
+/// 已排序的词库条目(词组, 编码, 优先级)
+pub(crate) type SortedItems = Vec<(String, String, usize)>;
+
+/// 将Rime格式词库内容中的每一行(词组, 编码, 优先级)解析并返回一个HashSet
+pub(crate) fn parse_rime_items(content: &str, capacity: usize) -> HashSet<(String, String, usize)> {
     let mut items = HashSet::with_capacity(capacity);
-    for line in BufReader::new(file).lines() {
-        let line = line.map_err(|_| "无法读取文件中的一行")?;
+    for line in content.lines() {
         let item = line.split('#').next().expect("无法解析文件中的注释");
         let parts: Vec<&str> = item.split('\t').collect();
         if parts.len() == 2 {
@@ -24,34 +30,72 @@ pub(crate) fn read_rime_file(
             ));
         }
     }
-    Ok(items)
+    items
+}
+
+/// 读取并解析Rime格式词库文件
+pub(crate) fn read_rime_path(
+    path: &PathBuf,
+    encoding: &str,
+) -> Result<HashSet<(String, String, usize)>, &'static str> {
+    let bytes = std::fs::read(path).map_err(|_| "无法读取词库文件")?;
+    Ok(parse_rime_items(&decode(&bytes, encoding)?, 65536))
 }
 
-/// 返回按首字符分组的词库，以及其中词组的最大长度。子词库中的元素为(词组, 编码, 击键当量)
+/// 返回按首字符分组的词库，以及其中词组的最大长度
 pub(crate) fn load_dict(
     path: &PathBuf,
     punct_items: HashSet<(String, String, usize)>,
-    connector: RouteConnector, // 克隆一个，和用于编码的连接器区分开，不要借用
-) -> Result<(HashMap<char, Vec<(Vec<char>, Vec<char>, f64)>>, usize), &'static str> {
-    println!("读取词库文件...");
-    let file = File::open(path).map_err(|_| "无法打开词库文件")?;
-    let dict_items = read_rime_file(file, 65536)?;
-    println!("读取完成。共{}个条目。", dict_items.len());
-    println!("结合标点符号排序并生成翻页、选重信息...");
-    let sorted_dict_items = sort_items(&dict_items);
-    let sorted_punct_items = sort_items(&punct_items);
-    let (dict, max_word_len) = convert_items(sorted_dict_items, sorted_punct_items, connector);
-    if dict.is_empty() {
-        return Err("词库为空");
+    mut connector: RouteConnector, // 克隆一个，和用于编码的连接器区分开，不要借用
+) -> Result<(Dict, usize), &'static str> {
+    let encoding = connector.settings().dict_encoding.clone();
+    let (sorted_dict_items, sorted_punct_items) = load_sorted_items(path, &punct_items, &encoding)?;
+    println!("结合标点符号生成翻页、选重信息...");
+    let (dict, max_word_len) = build_dict(&sorted_dict_items, &sorted_punct_items, &mut connector)?;
+    let word_count: usize = dict.values().map(|sub_dict| sub_dict.len()).sum();
+    println!("整理后共{}个最优词组。", word_count);
+    if connector.unknown_keys_count() == 0 {
+        println!("编码中没有遇到找不到当量的按键组合。");
+    } else {
+        println!(
+            "编码中遇到{}个找不到当量的按键组合。",
+            connector.unknown_keys_count()
+        );
     }
+    println!("最大词组长度为{}个字。", max_word_len);
     println!("处理完成。首字共覆盖{}个字符。", dict.len());
     Ok((dict, max_word_len))
 }
 
+/// 读取词库文件，返回排序后的词库条目和标点符号条目
+pub(crate) fn load_sorted_items(
+    path: &PathBuf,
+    punct_items: &HashSet<(String, String, usize)>,
+    encoding: &str,
+) -> Result<(SortedItems, SortedItems), &'static str> {
+    println!("读取词库文件...");
+    let dict_items = read_rime_path(path, encoding)?;
+    println!("读取完成。共{}个条目。排序...", dict_items.len());
+    Ok((sort_items(&dict_items), sort_items(punct_items)))
+}
+
+/// 由排序后的条目生成词库。连接器用于计算各编码的当量，决定多编码词组使用哪个编码
+pub(crate) fn build_dict(
+    dict_items: &SortedItems,
+    punct_items: &SortedItems,
+    connector: &mut RouteConnector,
+) -> Result<(Dict, usize), &'static str> {
+    let (dict, max_word_len) = convert_items(dict_items, punct_items, connector);
+    match dict.is_empty() {
+        true => Err("词库为空"),
+        false => Ok((dict, max_word_len)),
+    }
+}
+
 /// 排序条目。顺序：优先级降序、码长升序、词升序、码升序
-fn sort_items(items: &HashSet<(String, String, usize)>) -> Vec<(String, String, usize)> {
+fn sort_items(items: &HashSet<(String, String, usize)>) -> SortedItems {
     let code_len = |w: &str, c: &str| c.len() as f64 / w.len() as f64;
-    let mut sorted: Vec<_> = items.into_iter().cloned().collect();
+    let mut sorted: Vec<_> = items.iter().cloned().collect();
     sorted.sort_by(|(w1, c1, p1), (w2, c2, p2)| {
         p2.cmp(p1)
             .then(
@@ -65,46 +109,76 @@ fn sort_items(items: &HashSet<(String, String, usize)>) -> Vec<(String, String,
     sorted
 }
 
-/// 转换条目为按首字符分组的词库，并返回其中词组的最大长度。子词库中的元素为(词组, 编码, 击键当量)
+/// 生成的选重键和翻页键的额外当量，即看候选栏找字的时间。选重按候选位置计，翻页按次数计
+fn pick_penalty(settings: &Settings, code: &[char], picks: Picks) -> f64 {
+    let selection_penalty = match (picks.0, code.last().and_then(|c| c.to_digit(10))) {
+        (1, Some(position)) => settings.selection_penalty(position as usize),
+        _ => 0.0,
+    };
+    selection_penalty + picks.1 as f64 * settings.page_penalty
+}
+
+/// 计算条目的目标值：当量、码数、选重次数、翻页次数的加权和，与编码时的目标值相同
+fn entry_score(settings: &Settings, code: &[char], time: f64, picks: Picks) -> f64 {
+    settings.obj_time_weight * time
+        + settings.obj_key_weight * code.len() as f64
+        + settings.obj_selection_weight * picks.0 as f64
+        + settings.obj_page_weight * picks.1 as f64
+}
+
+/// 转换条目为按首字符分组的词库，并返回其中词组的最大长度
 fn convert_items(
-    dict_items: Vec<(String, String, usize)>, // 已经过排序，优先级无用
-    punct_items: Vec<(String, String, usize)>, // 已经过排序，优先级无用
-    mut connector: RouteConnector,
-) -> (HashMap<char, Vec<(Vec<char>, Vec<char>, f64)>>, usize) {
-    // 生成唯一编码的方法
+    dict_items: &SortedItems,  // 已经过排序，优先级无用
+    punct_items: &SortedItems, // 已经过排序，优先级无用
+    connector: &mut RouteConnector,
+) -> (Dict, usize) {
+    // 生成唯一编码的方法，同时返回其中生成的选重键和翻页键的个数
     let count = dict_items.len() + punct_items.len();
     let mut used_codes = HashSet::with_capacity(count);
+    let chords = connector.chords().clone(); // 与连接器共用同一张并击表
     let mut get_unique_code = |code: &str| {
-        let mut unique_code = code.to_string();
+        let mut unique_code = chords.parse_code(code); // 并击解析为单个字符，避免键序不同的同一并击占用两个码位
+        let mut picks = (0, 0);
         let mut i: u8 = 2;
         while used_codes.contains(&unique_code) {
             if i == 2 {
                 unique_code.push('2');
+                picks.0 = 1;
             } else if i < 10 {
                 unique_code.pop();
-                unique_code.push(('0' as u8 + i) as char);
+                unique_code.push((b'0' + i) as char);
             } else {
                 unique_code.pop();
                 unique_code.push('='); // 等号翻页
+                picks = (0, picks.1 + 1);
                 i = 1;
             }
             i += 1;
         }
         used_codes.insert(unique_code.clone()); // 码位被占用，但不代表会用到这个编码
-        unique_code
+        (unique_code, picks)
     };
 
-    // 装填词库的方法，编码拆成数组
-    let mut mid_dict: HashMap<String, (Vec<char>, f64)> = HashMap::with_capacity(65536);
-    let mut add_item = |word: String, code: String| {
-        let new_code: Vec<char> = get_unique_code(&code).chars().collect();
-        let new_time = connector.get_time(&new_code);
-        if let Some((old_code, old_time)) = mid_dict.get(&word) {
-            if new_time < *old_time || new_code.len() < old_code.len() {
-                mid_dict.insert(word, (new_code, new_time));
+    // 装填词库的方法，编码拆成数组。当量含选重和翻页的惩罚
+    let mut mid_dict: HashMap<String, (Vec<char>, f64, Picks)> = HashMap::with_capacity(65536);
+    let mut add_item = |word: &str, code: &str| {
+        let (new_code, picks) = get_unique_code(code);
+        let penalty = pick_penalty(connector.settings(), &new_code, picks);
+        let new_time = connector.get_time(&new_code) + penalty;
+        // 与编码时比较路径的规则相同：目标值更小，或同目标值而当量更小，或再同当量且编码更短
+        if let Some((old_code, old_time, old_picks)) = mid_dict.get(word) {
+            let settings = connector.settings();
+            let new_score = entry_score(settings, &new_code, new_time, picks);
+            let old_score = entry_score(settings, old_code, *old_time, *old_picks);
+            if compare(new_score, old_score)
+                .then(compare(new_time, *old_time))
+                .then(new_code.len().cmp(&old_code.len()))
+                .is_lt()
+            {
+                mid_dict.insert(word.to_string(), (new_code, new_time, picks));
             }
         } else {
-            mid_dict.insert(word, (new_code, new_time));
+            mid_dict.insert(word.to_string(), (new_code, new_time, picks));
         }
     };
 
@@ -115,29 +189,39 @@ fn convert_items(
     for (punct, code, _) in punct_items {
         add_item(punct, code);
     }
-    println!("整理后共{}个最优词组。", mid_dict.len());
-    if connector.unknown_keys_count() == 0 {
-        println!("编码中没有遇到找不到当量的按键组合。");
-    } else {
-        println!(
-            "编码中遇到{}个找不到当量的按键组合。",
-            connector.unknown_keys_count()
-        );
+
+    // 文本中的换行、空格、制表符按设置的按键直接键入，不经过候选，不占码位。词库中已有的不替换
+    let settings = connector.settings();
+    let enter_keys = settings.enter_keys.clone();
+    let whitespace = [
+        ("\r\n", enter_keys.clone()),
+        ("\n", enter_keys),
+        (" ", settings.space_keys.clone()),
+        ("\t", settings.tab_keys.clone()),
+    ];
+    for (word, keys) in whitespace {
+        if !keys.is_empty() && !mid_dict.contains_key(word) {
+            let time = connector.get_time(&keys);
+            mid_dict.insert(word.to_string(), (keys, time, (0, 0)));
+        }
     }
 
     // 词组拆成数组，按首字分组，并记录最长词组长度
     let mut max_word_len = 0;
     let mut master_dict: HashMap<char, Vec<_>> = HashMap::with_capacity(16384);
-    for (word, (code, time)) in mid_dict {
+    for (word, (code, time, picks)) in mid_dict {
         let word_chars: Vec<char> = word.chars().collect();
         max_word_len = max_word_len.max(word_chars.len());
         if let Some(sub_dict) = master_dict.get_mut(&word_chars[0]) {
-            sub_dict.push((word_chars, code, time));
+            sub_dict.push((word_chars, code, time, picks));
         } else {
-            master_dict.insert(word_chars[0], vec![(word_chars, code, time)]);
+            master_dict.insert(word_chars[0], vec![(word_chars, code, time, picks)]);
         }
     }
-    println!("最大词组长度为{}个字。", max_word_len);
+    // 子词库按词组、编码排序，使编码结果不受哈希表遍历顺序影响
+    for sub_dict in master_dict.values_mut() {
+        sub_dict.sort_by(|(w1, c1, _, _), (w2, c2, _, _)| w1.cmp(w2).then(c1.cmp(c2)));
+    }
 
     (master_dict, max_word_len)
 }
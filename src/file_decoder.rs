@@ -0,0 +1,123 @@
+use encoding_rs::{Encoding, GB18030, UTF_8, UTF_16BE, UTF_16LE};
+
+/// 自动识别编码时，用于判断UTF-16的取样字数
+const SAMPLE_CHARS: usize = 4096;
+/// 取样中常见字符的比例不低于此值时，才认为是UTF-16
+const PLAUSIBLE_RATIO: f64 = 0.95;
+
+/// 是否为文本中常见的字符：空白、ASCII可见字符、中日韩文字及标点、全角字符
+fn is_plausible(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_ascii_graphic()
+        || matches!(c, '\u{3000}'..='\u{303F}' | '\u{3400}'..='\u{9FFF}' | '\u{FF00}'..='\u{FFEF}')
+}
+
+/// 没有BOM的UTF-16：按字节序解码取样，常见字符足够多时认为是这种编码
+fn looks_like_utf16(bytes: &[u8], encoding: &'static Encoding) -> bool {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return false;
+    }
+    let sample = &bytes[..bytes.len().min(SAMPLE_CHARS * 2)];
+    let (text, _) = encoding.decode_without_bom_handling(sample); // 无法解码的字节成为替换字符，不算常见字符
+    let total = text.chars().count();
+    let plausible = text.chars().filter(|c| is_plausible(*c)).count();
+    total > 0 && plausible as f64 / total as f64 >= PLAUSIBLE_RATIO
+}
+
+/// 自动识别编码：有BOM则按BOM；否则依次尝试UTF-8、没有BOM的UTF-16、GB18030（兼容GBK和GB2312）。
+/// 以ASCII为主的UTF-16也是合法的UTF-8（空字节合法），所以取样中有空字节时先尝试UTF-16，
+/// 空字节多在奇数位时先试小端序，否则先试大端序
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    let sample = &bytes[..bytes.len().min(SAMPLE_CHARS * 2)];
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|b| **b == 0)
+        .count();
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let utf16 = match odd_zeros >= even_zeros {
+        true => [UTF_16LE, UTF_16BE],
+        false => [UTF_16BE, UTF_16LE],
+    };
+    if odd_zeros + even_zeros > 0
+        && let Some(encoding) = utf16.into_iter().find(|e| looks_like_utf16(bytes, e))
+    {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    utf16
+        .into_iter()
+        .find(|encoding| looks_like_utf16(bytes, encoding))
+        .unwrap_or(GB18030)
+}
+
+/// 按编码名称解码文件内容，去掉开头的BOM。名称为“自动”时自动识别
+pub(crate) fn decode(bytes: &[u8], encoding: &str) -> Result<String, &'static str> {
+    let encoding = match encoding {
+        "" | "自动" => detect(bytes),
+        label => Encoding::for_label(label.as_bytes()).ok_or("未知的文件编码")?,
+    };
+    let (content, _, had_errors) = encoding.decode(bytes);
+    match had_errors {
+        true => Err("文件中有无法解码的字节，请在设置中指定正确的编码"),
+        false => Ok(content.into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "a\tbc\t100\nhello world\n";
+    const CJK: &str = "我们今天去公园散步，天气很好。\n这是一段测试文本。\n";
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|u| match big_endian {
+                true => u.to_be_bytes(),
+                false => u.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect_bom() {
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice(CJK.as_bytes());
+        assert_eq!(detect(&bytes), UTF_8);
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(utf16(ASCII, false));
+        assert_eq!(detect(&bytes), UTF_16LE);
+        let mut bytes = vec![0xFE, 0xFF];
+        bytes.extend(utf16(CJK, true));
+        assert_eq!(detect(&bytes), UTF_16BE);
+    }
+
+    #[test]
+    fn detect_utf8() {
+        assert_eq!(detect(ASCII.as_bytes()), UTF_8);
+        assert_eq!(detect(CJK.as_bytes()), UTF_8);
+    }
+
+    #[test]
+    fn detect_utf16_without_bom() {
+        for text in [ASCII, CJK] {
+            assert_eq!(detect(&utf16(text, false)), UTF_16LE);
+            assert_eq!(detect(&utf16(text, true)), UTF_16BE);
+        }
+    }
+
+    #[test]
+    fn detect_gbk() {
+        let (bytes, _, _) = GB18030.encode(CJK);
+        assert_eq!(detect(&bytes), GB18030);
+        assert_eq!(decode(&bytes, "自动").unwrap(), CJK);
+        assert_eq!(decode(&bytes, "gbk").unwrap(), CJK);
+    }
+}
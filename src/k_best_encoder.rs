@@ -0,0 +1,96 @@
+use crate::dict_loader::Dict;
+use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+
+/// 比较当量时容许的浮点误差
+const EPSILON: f64 = 1e-6;
+
+/// 一条候选路径：(编码, 当量, 各段的字数)
+pub(crate) type Candidate = (Vec<char>, f64, Vec<usize>);
+
+/// 按当量、码长、编码排序，去掉编码相同的路径，只保留前k条、且当量不超过最优路径加slack的路径
+fn prune(mut candidates: Vec<Candidate>, k: usize, slack: f64) -> Vec<Candidate> {
+    candidates.sort_by(|(k1, t1, _), (k2, t2, _)| {
+        t1.partial_cmp(t2)
+            .expect("无法比较当量")
+            .then(k1.len().cmp(&k2.len()))
+            .then(k1.cmp(k2))
+    });
+    candidates.dedup_by(|(k1, _, _), (k2, _, _)| k1 == k2);
+    candidates.truncate(k);
+    if let Some(best) = candidates.first().map(|(_, time, _)| *time) {
+        candidates.retain(|(_, time, _)| *time <= best + slack + EPSILON);
+    }
+    candidates
+}
+
+/// 计算一段文本当量最小的若干条编码路径，按当量升序。
+/// 没有设置候选路径范围时，每个位置保留至多k条路径，所以与逐位置只保留最优路径的编码器一样，结果是近似的。
+/// 设置了范围时，先求出最优路径的当量，再在每个位置保留当量不超过该位置最优路径加上相应余量的所有路径，
+/// 不再受k限制，返回当量不超过最优路径相应百分比的路径
+pub(crate) fn encode(
+    text: &[char],
+    dict: &Dict,
+    connector: &mut RouteConnector,
+    settings: &Settings,
+) -> Result<Vec<Candidate>, &'static str> {
+    if settings.alt_range <= 0.0 {
+        return encode_pruned(
+            text,
+            dict,
+            connector,
+            settings.alt_count.max(1),
+            f64::INFINITY,
+        );
+    }
+    let best = encode_pruned(text, dict, connector, 1, f64::INFINITY)?[0].1;
+    let slack = best * settings.alt_range / 100.0;
+    encode_pruned(text, dict, connector, usize::MAX, slack)
+}
+
+/// 逐位置按prune的规则保留路径，返回到达文本末尾的路径
+fn encode_pruned(
+    text: &[char],
+    dict: &Dict,
+    connector: &mut RouteConnector,
+    k: usize,
+    slack: f64,
+) -> Result<Vec<Candidate>, &'static str> {
+    let mut routes: Vec<Vec<Candidate>> = vec![Vec::new(); text.len() + 1];
+    routes[0].push((Vec::new(), 0.0, Vec::new()));
+
+    for i in 0..text.len() {
+        let current = prune(std::mem::take(&mut routes[i]), k, slack);
+        if current.is_empty() {
+            continue; // 位于词组中间，无法到达
+        }
+
+        // 从这里开始的词组。没有则直接输出原字符
+        let mut words: Vec<(usize, &[char], f64)> = match dict.get(&text[i]) {
+            Some(sub_dict) => sub_dict
+                .iter()
+                .filter(|(word, _, _, _)| text[i..].starts_with(word))
+                .map(|(word, code, time, _)| (word.len(), &code[..], *time))
+                .collect(),
+            None => Vec::new(),
+        };
+        if words.is_empty() {
+            words.push((1, &text[i..i + 1], 0.0));
+        }
+
+        for (keys, time, lens) in &current {
+            for (len, code, code_time) in &words {
+                let (new_keys, new_time) = connector.connect(keys, code, *time, *code_time);
+                let mut new_lens = lens.clone();
+                new_lens.push(*len);
+                routes[i + len].push((new_keys, new_time, new_lens));
+            }
+        }
+    }
+
+    let candidates = prune(std::mem::take(&mut routes[text.len()]), k, slack);
+    match candidates.is_empty() {
+        true => Err("无法编码文本"),
+        false => Ok(candidates),
+    }
+}
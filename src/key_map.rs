@@ -0,0 +1,75 @@
+use crate::chord::Chords;
+use crate::file_decoder::decode;
+use std::collections::{HashMap, HashSet};
+use std::fs::read;
+use std::path::PathBuf;
+
+/// 按键映射：键为编码中的按键，值为实际按下的物理按键。没有映射的按键保持不变
+pub(crate) type KeyMap = HashMap<char, char>;
+
+/// 读取按键映射文件。每行格式为`编码中的按键\t物理按键`，用`#`号引导注释，空格可写作“空格”
+pub(crate) fn load_key_map(path: &PathBuf, encoding: &str) -> Result<KeyMap, &'static str> {
+    println!("读取按键映射文件...");
+    let bytes = read(path).map_err(|_| "无法读取按键映射文件")?;
+    let content = decode(&bytes, encoding)?;
+    let parse_key = |s: &str| match s {
+        "空格" => Some(' '),
+        s if s.chars().count() == 1 => s.chars().next(),
+        _ => None,
+    };
+
+    let mut key_map = HashMap::with_capacity(64);
+    let mut targets = HashSet::with_capacity(64);
+    for line in content.lines() {
+        if line.is_empty() || (line.starts_with('#') && !line.starts_with("#\t")) {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((from, to)) => match (parse_key(from), parse_key(to)) {
+                (Some(from), Some(to)) => {
+                    if key_map.insert(from, to).is_some() {
+                        println!("按键映射文件中有重复的按键：{from}");
+                    }
+                    if !targets.insert(to) {
+                        println!("按键映射文件中有多个按键映射到：{to}");
+                    }
+                }
+                _ => println!("按键映射文件中有格式错误的行：{line}"),
+            },
+            None => println!("按键映射文件中有格式错误的行：{line}"),
+        }
+    }
+    println!("读取完成。共{}个按键。", key_map.len());
+    Ok(key_map)
+}
+
+/// 映射一个按键。并击映射其中的每个键
+pub(crate) fn map_key(c: char, key_map: &KeyMap, chords: &Chords) -> char {
+    if key_map.is_empty() {
+        return c;
+    }
+    match chords.keys_of(c) {
+        Some(keys) => {
+            let mapped: Vec<char> = keys.iter().map(|k| *key_map.get(k).unwrap_or(k)).collect();
+            chords.register(&mapped)
+        }
+        None => *key_map.get(&c).unwrap_or(&c),
+    }
+}
+
+/// 预先映射按键映射中的按键和已登记的并击，结果以编码中的按键为键。不映射时为空
+pub(crate) fn precompute(key_map: &KeyMap, chords: &Chords) -> HashMap<char, char> {
+    if key_map.is_empty() {
+        return HashMap::new();
+    }
+    let mut mapped = key_map.clone();
+    for (c, _) in chords.registered() {
+        mapped.insert(c, map_key(c, key_map, chords));
+    }
+    mapped
+}
+
+/// 映射整段编码
+pub(crate) fn map_keys(keys: &[char], key_map: &KeyMap, chords: &Chords) -> Vec<char> {
+    keys.iter().map(|c| map_key(*c, key_map, chords)).collect()
+}
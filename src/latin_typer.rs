@@ -0,0 +1,118 @@
+use crate::chord::Chords;
+use crate::settings::Settings;
+use std::collections::HashMap;
+
+/// 美式键盘上需要按住Shift键入的符号，及其所在的键
+const SHIFTED: [(char, char); 21] = [
+    ('~', '`'),
+    ('!', '1'),
+    ('@', '2'),
+    ('#', '3'),
+    ('$', '4'),
+    ('%', '5'),
+    ('^', '6'),
+    ('&', '7'),
+    ('*', '8'),
+    ('(', '9'),
+    (')', '0'),
+    ('_', '-'),
+    ('+', '='),
+    ('{', '['),
+    ('}', ']'),
+    ('|', '\\'),
+    (':', ';'),
+    ('"', '\''),
+    ('<', ','),
+    ('>', '.'),
+    ('?', '/'),
+];
+
+/// 直接键入的一段西文
+pub(crate) struct Piece {
+    /// 字数
+    pub(crate) len: usize,
+    /// 按键，含首尾的中英切换按键
+    pub(crate) keys: Vec<char>,
+    /// 中英切换额外计入的当量
+    pub(crate) switch_time: f64,
+    /// 是否接续同一片段的上一段。接续的段不按连接方法连接，直接接在上文之后
+    pub(crate) continued: bool,
+}
+
+/// 西文状态下键入一个字符的按键：大写字母和上档符号为修饰键中的第一个（即Shift）与所在键的并击
+fn key_of(c: char, shift: Option<char>, chords: &Chords) -> char {
+    let base = match c.is_ascii_uppercase() {
+        true => Some(c.to_ascii_lowercase()),
+        false => SHIFTED.iter().find(|(s, _)| *s == c).map(|(_, k)| *k),
+    };
+    match (base, shift) {
+        (Some(base), Some(shift)) => chords.register(&[shift, base]),
+        _ => c,
+    }
+}
+
+/// 寻找含字母的西文片段，返回各片段的(起点, 终点)。片段由ASCII可见字符和其间的空格组成，首尾不含空格
+pub(crate) fn find_runs(text: &[char]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        if !text[i].is_ascii_graphic() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        for (j, c) in text.iter().enumerate().skip(i + 1) {
+            match c {
+                ' ' => {}
+                c if c.is_ascii_graphic() => end = j + 1,
+                _ => break,
+            }
+        }
+        if text[start..end].iter().any(char::is_ascii_alphabetic) {
+            runs.push((start, end));
+        }
+        i = end;
+    }
+    runs
+}
+
+/// 把文本中的西文片段拆成不超过max_len字的段，以起点为键。不直接键入西文时为空
+pub(crate) fn split_pieces(
+    text: &[char],
+    settings: &Settings,
+    chords: &Chords,
+    max_len: usize,
+) -> HashMap<usize, Piece> {
+    let mut pieces = HashMap::new();
+    if !settings.latin_direct {
+        return pieces;
+    }
+    let shift = settings.modifiers.first().copied();
+    let switch_keys = &settings.ime_switch_keys;
+    for (start, end) in find_runs(text) {
+        let mut i = start;
+        while i < end {
+            let len = max_len.max(1).min(end - i);
+            let (first, last) = (i == start, i + len == end);
+            let mut keys = Vec::with_capacity(len + 2 * switch_keys.len());
+            if first {
+                keys.extend_from_slice(switch_keys);
+            }
+            keys.extend(text[i..i + len].iter().map(|c| key_of(*c, shift, chords)));
+            if last {
+                keys.extend_from_slice(switch_keys);
+            }
+            let switch_count = first as usize + last as usize;
+            let piece = Piece {
+                len,
+                keys,
+                switch_time: switch_count as f64 * settings.ime_switch_time,
+                continued: !first,
+            };
+            pieces.insert(i, piece);
+            i += len;
+        }
+    }
+    pieces
+}
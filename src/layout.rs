@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+/// 旧版布局文件中各排起始位置的横向错位，单位为键宽
+const ROW_STAGGER: [f64; 5] = [0.0, 0.5, 0.75, 1.25, 5.5];
+
+pub(crate) const FINGER_NAMES: [&str; 5] = ["小", "无", "中", "食", "拇"];
+
+/// 拇指在手指编号中的位置
+pub(crate) const THUMB: usize = 4;
+
+/// 按键的手、指、排、列和物理坐标
+#[derive(Clone, Copy)]
+pub(crate) struct KeyInfo {
+    /// 0为左手，1为右手。没有指定（如旧版布局中的拇指键）为None
+    pub(crate) hand: Option<usize>,
+    /// 从小指到拇指为0~4
+    pub(crate) finger: Option<usize>,
+    /// 从数字排到底排为0~4
+    pub(crate) row: Option<usize>,
+    /// 列号，可以为负数
+    pub(crate) column: Option<i32>,
+    /// 物理坐标(x, y)，单位为键宽，y向下增大
+    pub(crate) coordinates: Option<(f64, f64)>,
+}
+
+impl KeyInfo {
+    fn new() -> Self {
+        Self {
+            hand: None,
+            finger: None,
+            row: None,
+            column: None,
+            coordinates: None,
+        }
+    }
+
+    /// 物理坐标。没有定义时，按列和排推算
+    pub(crate) fn position(&self) -> Option<(f64, f64)> {
+        match (self.coordinates, self.column, self.row) {
+            (Some(xy), _, _) => Some(xy),
+            (None, Some(column), Some(row)) => Some((column as f64, row as f64)),
+            _ => None,
+        }
+    }
+
+    /// 是否由同一只手的同一根手指按下
+    pub(crate) fn same_finger(&self, other: &KeyInfo) -> bool {
+        self.finger.is_some() && self.finger == other.finger && self.hand == other.hand
+    }
+
+    pub(crate) fn is_thumb(&self) -> bool {
+        self.finger == Some(THUMB)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Layout {
+    keys: HashMap<char, KeyInfo>,
+}
+
+impl Layout {
+    pub(crate) fn new() -> Self {
+        Self {
+            keys: HashMap::with_capacity(64),
+        }
+    }
+
+    /// 从14行的旧版布局文件内容生成：前5行为各排的码元，后9行为各手指的码元。
+    /// 列号为码元在所在排中的序号，坐标按常见键盘的错位推算
+    pub(crate) fn from_lines(lines: &[String]) -> Self {
+        const FINGERS: [(Option<usize>, usize); 9] = [
+            (Some(0), 0),
+            (Some(0), 1),
+            (Some(0), 2),
+            (Some(0), 3),
+            (Some(1), 3),
+            (Some(1), 2),
+            (Some(1), 1),
+            (Some(1), 0),
+            (None, THUMB),
+        ];
+
+        let mut keys: HashMap<char, KeyInfo> = HashMap::with_capacity(64);
+        for (i, line) in lines.iter().enumerate().take(14) {
+            for (j, c) in line.chars().enumerate() {
+                let info = keys.entry(c).or_insert(KeyInfo::new());
+                if i < 5 && info.row.is_none() {
+                    info.row = Some(i);
+                    info.column = Some(j as i32);
+                    info.coordinates = Some((j as f64 + ROW_STAGGER[i], i as f64));
+                } else if i >= 5 && info.finger.is_none() {
+                    (info.hand, info.finger) = (FINGERS[i - 5].0, Some(FINGERS[i - 5].1));
+                }
+            }
+        }
+        Self { keys }
+    }
+
+    /// 解析新版布局文件中的一行：`键\t手\t指\t排\t列[\tx\ty]`。
+    /// 手为左、右或-；指为小、无、中、食、拇；排为0~4；键为空格时可写作“空格”
+    pub(crate) fn add_line(&mut self, line: &str) -> Result<(), &'static str> {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 5 && parts.len() != 7 {
+            return Err("应为5列或7列");
+        }
+
+        let key = match parts[0] {
+            "空格" => ' ',
+            s if s.chars().count() == 1 => s.chars().next().expect("无法获取按键"),
+            _ => return Err("按键应为单个字符"),
+        };
+        let hand = match parts[1] {
+            "左" => Some(0),
+            "右" => Some(1),
+            "-" => None,
+            _ => return Err("手应为左、右或-"),
+        };
+        let finger = FINGER_NAMES
+            .iter()
+            .position(|name| *name == parts[2])
+            .ok_or("手指应为小、无、中、食或拇")?;
+        let row = match parts[3].parse() {
+            Ok(row) if row < 5 => row,
+            _ => return Err("排应为0~4"),
+        };
+        let column = parts[4].parse().map_err(|_| "列应为整数")?;
+        let coordinates = if parts.len() == 7 {
+            let x = parts[5].parse().map_err(|_| "横坐标应为数值")?;
+            let y = parts[6].parse().map_err(|_| "纵坐标应为数值")?;
+            Some((x, y))
+        } else {
+            None
+        };
+
+        if self.keys.contains_key(&key) {
+            return Err("按键重复");
+        }
+        let info = KeyInfo {
+            hand,
+            finger: Some(finger),
+            row: Some(row),
+            column: Some(column),
+            coordinates,
+        };
+        self.keys.insert(key, info);
+        Ok(())
+    }
+
+    /// 布局中的所有按键，按字符排序
+    pub(crate) fn keys(&self) -> Vec<char> {
+        let mut keys: Vec<char> = self.keys.keys().copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    pub(crate) fn get(&self, key: char) -> Option<&KeyInfo> {
+        self.keys.get(&key)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
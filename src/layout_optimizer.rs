@@ -0,0 +1,126 @@
+use crate::dict_loader::{SortedItems, build_dict};
+use crate::key_map::{self, KeyMap};
+use crate::route_buffer::RouteBuffer;
+use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+use crate::text_encoder::encode_chars;
+
+/// 可复现的伪随机数生成器（xorshift64*），同一种子总是得到同一序列
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self((seed ^ 0x9E37_79B9_7F4A_7C15) | 1) // 状态不能为0
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// [0, n)中的整数
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// [0, 1)中的小数
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// 用一种按键映射编码全文，返回(编码路径, 总当量)。词库随映射重新生成，因为多编码词组的取舍取决于当量
+pub(crate) fn encode_with_key_map(
+    text: &[char],
+    dict_items: &SortedItems,
+    punct_items: &SortedItems,
+    connector: &RouteConnector,
+    key_map: &KeyMap,
+) -> Result<(Vec<char>, f64), &'static str> {
+    let mut dict_connector = connector.with_key_map(key_map.clone());
+    let (dict, max_word_len) = build_dict(dict_items, punct_items, &mut dict_connector)?;
+    let buffer_connector = connector.with_key_map(key_map.clone());
+    let mut buffer = RouteBuffer::new(16.max(max_word_len), buffer_connector)?;
+    encode_chars(text, &dict, &mut buffer, false)
+}
+
+/// 用模拟退火搜索可移动按键在物理按键上的排列，使全文总当量最小。
+/// 每步交换两个可移动按键的物理位置，更优则接受，更差则按温度以一定概率接受；温度按指数逐步降低。
+/// 返回当量最小的若干种按键映射及其当量，按当量升序
+pub(crate) fn optimize(
+    text: &[char],
+    dict_items: &SortedItems,
+    punct_items: &SortedItems,
+    connector: &RouteConnector,
+    initial: &KeyMap,
+    settings: &Settings,
+) -> Result<Vec<(KeyMap, f64)>, &'static str> {
+    // 可移动的按键及其当前的物理位置
+    let keys: Vec<char> = settings
+        .opt_keys
+        .iter()
+        .copied()
+        .filter(|c| !settings.opt_pinned_keys.contains(c))
+        .collect();
+    if keys.len() < 2 {
+        return Err("可移动的按键不足2个");
+    }
+    let mut targets: Vec<char> = keys
+        .iter()
+        .map(|c| key_map::map_key(*c, initial, connector.chords()))
+        .collect();
+    let to_key_map = |targets: &[char]| {
+        let mut key_map = initial.clone();
+        key_map.extend(keys.iter().copied().zip(targets.iter().copied()));
+        key_map
+    };
+    let evaluate = |targets: &[char]| {
+        let key_map = to_key_map(targets);
+        encode_with_key_map(text, dict_items, punct_items, connector, &key_map).map(|(_, t)| t)
+    };
+
+    let mut rng = Rng::new(settings.opt_seed);
+    let mut current = evaluate(&targets)?;
+    println!("初始布局的当量为{current:.1}。开始搜索...");
+    let mut best: Vec<(Vec<char>, f64)> = vec![(targets.clone(), current)];
+
+    let iterations = settings.opt_iterations;
+    for i in 0..iterations {
+        // 温度为当量的相对变化，从设定值降至其1%
+        let progress = i as f64 / iterations.max(1) as f64;
+        let temperature = settings.opt_temperature * 0.01f64.powf(progress);
+
+        let (a, b) = (rng.below(keys.len()), rng.below(keys.len()));
+        if a == b {
+            continue;
+        }
+        targets.swap(a, b);
+        let time = evaluate(&targets)?;
+        let delta = (time - current) / current.max(f64::EPSILON); // 当量为0时避免除以0
+        if delta <= 0.0 || rng.next_f64() < (-delta / temperature).exp() {
+            current = time;
+            if !best.iter().any(|(t, _)| *t == targets) {
+                best.push((targets.clone(), time));
+                best.sort_by(|(_, t1), (_, t2)| t1.partial_cmp(t2).expect("无法比较当量"));
+                best.truncate(settings.opt_keep.max(1));
+            }
+        } else {
+            targets.swap(a, b); // 不接受，换回
+        }
+
+        if i % 10 == 0 {
+            print!(
+                "\r已迭代{i}/{iterations}次。当前当量{current:.1}，最小当量{:.1}。",
+                best[0].1
+            );
+        }
+    }
+    println!("\n搜索完成。");
+
+    Ok(best
+        .into_iter()
+        .map(|(targets, time)| (to_key_map(&targets), time))
+        .collect())
+}
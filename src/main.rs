@@ -1,11 +1,42 @@
+mod batch_comparer;
+mod chord;
 mod code_analyzer;
 mod config_loader;
+mod connector_script;
 mod console_reader;
+mod corpus_encoder;
 mod dict_loader;
+mod file_decoder;
+mod k_best_encoder;
+mod key_map;
+mod latin_typer;
+mod layout;
+mod layout_optimizer;
 mod report_saver;
+mod route_annotator;
 mod route_buffer;
 mod route_connector;
+mod settings;
+mod short_code_optimizer;
 mod text_encoder;
+mod text_preprocessor;
+mod time_map;
+mod time_map_fitter;
+mod time_map_generator;
+mod time_map_validator;
+mod typist_simulator;
+mod usage_analyzer;
+
+use chord::Chords;
+use layout::Layout;
+use route_connector::RouteConnector;
+use settings::Settings;
+
+fn exit_with_error<T>(message: &str) -> T {
+    println!("程序异常中止！错误信息：{message}");
+    console_reader::read_line();
+    std::process::exit(1);
+}
 
 fn main() {
     println!("欢迎使用code_racer赛码器！");
@@ -13,39 +44,507 @@ fn main() {
     println!("作者：GarthTB <g-art-h@outlook.com>");
     println!("源码：https://github.com/GarthTB/code_racer");
 
-    fn exit_with_error<T>(message: &str) -> T {
-        println!("程序异常中止！错误信息：{message}");
-        console_reader::read_line();
-        std::process::exit(1);
+    // 加载通用的配置文件
+    let settings = config_loader::load_settings().unwrap_or_else(exit_with_error);
+    let chords = Chords::new(&settings);
+    let layout = config_loader::load_layout(&settings).unwrap_or_else(exit_with_error);
+
+    match console_reader::get_mode() {
+        0 => encode_text(settings, layout, chords),
+        1 => generate_time_map(settings, layout, chords),
+        2 => validate_time_map(settings, layout, chords),
+        3 => fit_time_map(settings),
+        4 => optimize_layout(settings, layout, chords),
+        5 => optimize_short_codes(settings, layout, chords),
+        6 => list_alternatives(settings, layout, chords),
+        7 => simulate_typist(settings, layout, chords),
+        8 => encode_corpus(settings, layout, chords),
+        9 => compare_batch(settings, layout, chords),
+        _ => unreachable!("未知的功能代号"),
     }
 
-    // 加载配置文件
-    let layout = config_loader::load_layout().unwrap_or_else(exit_with_error);
-    let punct_items = config_loader::load_punct_items().unwrap_or_else(exit_with_error);
-    let time_map = config_loader::load_time_map().unwrap_or_else(exit_with_error);
+    println!("程序执行完毕。按回车键退出...");
+    console_reader::read_line();
+}
+
+/// 计算输入整篇文本所需最小当量的编码，并分析、保存
+fn encode_text(settings: Settings, layout: Layout, chords: Chords) {
+    // 加载其余配置文件
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
 
     // 读取输入并加载其余配置
-    let connector = console_reader::get_connector(time_map);
+    let key_map = console_reader::get_key_map(&settings);
+    let connector = console_reader::get_connector(
+        time_map,
+        chords,
+        settings.clone(),
+        layout.clone(),
+        key_map.clone(),
+    );
     let (dict, max_word_len) = console_reader::get_dict(punct_items, connector.clone());
     let text_path = console_reader::get_text_path();
 
-    // 创建缓冲区，开始编码
-    let buffer_size = 16.max(max_word_len);
-    let mut buffer =
-        route_buffer::RouteBuffer::new(buffer_size, connector).unwrap_or_else(exit_with_error);
-    let (route, time) =
-        text_encoder::encode(&text_path, dict, &mut buffer).unwrap_or_else(exit_with_error);
-    let text_len = buffer.count();
+    // 开始编码
+    println!("计算编码...");
+    let (text, preprocessing) =
+        text_encoder::read_text(&text_path, &settings).unwrap_or_else(exit_with_error);
+    let encoded = text_encoder::encode(&text, &dict, connector, 16.max(max_word_len), true)
+        .unwrap_or_else(exit_with_error);
+    let (route, time, segments) = (encoded.route, encoded.time, encoded.segments);
+    let text_len = text.len();
 
     // 输出报告
-    let report = code_analyzer::analyze(layout, text_len, route, time);
+    let chords = encoded.connector.chords();
+    if let Some(range) = console_reader::get_annotation_range(text_len) {
+        let lines = route_annotator::annotate(&text, &route, &segments, chords, range);
+        report_saver::save(&text_path, "分段编码", lines);
+    }
+    let mut objective = code_analyzer::analyze_objective(&settings, time, route.len(), &segments);
+    if settings.latin_direct {
+        objective.extend(code_analyzer::analyze_latin(route.len(), time, &segments));
+    }
+    let mut report = code_analyzer::analyze(&layout, text_len, route, &key_map, chords, time);
+    report.extend(objective);
+    if !preprocessing.is_empty() {
+        report.push("---以下为文本预处理的改动---".to_string());
+        report.extend(preprocessing);
+    }
     report_saver::save(&text_path, "最小当量编码报告", report);
-    if buffer.unknown_keys_count() > 0
-        && console_reader::need_to_report_unknown_keys(buffer.unknown_keys_count())
-    {
-        buffer.report_unknown_keys(&text_path);
+    if console_reader::need_to_report_usage() {
+        let usage = usage_analyzer::analyze(&text, &dict, &segments, chords);
+        report_saver::save(&text_path, "词库使用报告", usage);
+    }
+    encoded.connector.report_script_errors();
+    let unknown_count = encoded.connector.unknown_keys_count();
+    if unknown_count > 0 && console_reader::need_to_report_unknown_keys(unknown_count) {
+        encoded.connector.report_unknown_keys(&text_path);
     }
+}
 
-    println!("程序执行完毕。按回车键退出...");
-    console_reader::read_line();
+/// 按键盘布局的几何模型生成击键当量表，保存在配置目录中
+fn generate_time_map(settings: Settings, layout: Layout, chords: Chords) {
+    if layout.is_empty() {
+        exit_with_error::<()>("键盘布局为空，无法生成击键当量表");
+    }
+    let time_map_path =
+        config_loader::get_config_path("time_map.txt").unwrap_or_else(exit_with_error);
+
+    let content = if console_reader::need_to_fill_gaps() {
+        let time_map =
+            config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+        let mut lines =
+            config_loader::load_time_map_lines(&settings).unwrap_or_else(exit_with_error);
+        let new_lines = time_map_generator::generate(&layout, &settings, Some(&time_map));
+        println!("生成完成。填补了{}个组合。", new_lines.len());
+        lines.extend(new_lines);
+        lines
+    } else {
+        let lines = time_map_generator::generate(&layout, &settings, None);
+        println!("生成完成。共{}个组合。", lines.len());
+        lines
+    };
+    report_saver::save(&time_map_path, "生成的击键当量", content);
+}
+
+/// 校验击键当量表对布局和词库编码的覆盖情况，报告保存在配置目录中
+fn validate_time_map(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+    let time_map_path =
+        config_loader::get_config_path("time_map.txt").unwrap_or_else(exit_with_error);
+
+    // 收集标点符号和各词库中的编码
+    let mut codes: Vec<Vec<char>> = punct_items
+        .iter()
+        .map(|(_, code, _)| chords.parse_code(code))
+        .collect();
+    for path in console_reader::get_paths("词库") {
+        match dict_loader::read_rime_path(&path, &settings.dict_encoding) {
+            Ok(items) => codes.extend(items.iter().map(|(_, code, _)| chords.parse_code(code))),
+            Err(message) => println!("无法读取词库{}。错误信息：{message}", path.display()),
+        }
+    }
+
+    println!("校验击键当量表...");
+    let report = time_map_validator::validate(&layout, &time_map, &chords, &settings, &codes);
+    println!("校验完成。");
+    report_saver::save(&time_map_path, "校验报告", report);
+}
+
+/// 由击键记录拟合个人的击键当量表，保存在配置目录中
+fn fit_time_map(settings: Settings) {
+    let time_map_path =
+        config_loader::get_config_path("time_map.txt").unwrap_or_else(exit_with_error);
+    let mut logs = Vec::new();
+    for path in console_reader::get_paths("击键记录") {
+        match time_map_fitter::read_log(&path, &settings.config_encoding) {
+            Ok(strokes) => logs.push(strokes),
+            Err(message) => println!("无法读取击键记录{}。错误信息：{message}", path.display()),
+        }
+    }
+
+    println!("拟合击键当量...");
+    let content = time_map_fitter::fit(&logs, &settings);
+    if content.is_empty() {
+        exit_with_error::<()>("击键记录中没有样本足够的组合");
+    }
+    println!("拟合完成。共{}个组合。", content.len());
+    report_saver::save(&time_map_path, "拟合的击键当量", content);
+}
+
+/// 搜索编码按键在物理按键上的最优排列，保存报告和最优的按键映射
+fn optimize_layout(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let initial = console_reader::get_key_map(&settings);
+    let connector = console_reader::get_connector(
+        time_map,
+        chords,
+        settings.clone(),
+        layout.clone(),
+        initial.clone(),
+    );
+    let (dict_items, punct_items) = console_reader::get_dict_items(&punct_items, &settings);
+    let text_path = console_reader::get_text_path();
+    let (text, _) = text_encoder::read_text(&text_path, &settings).unwrap_or_else(exit_with_error);
+
+    let results = layout_optimizer::optimize(
+        &text,
+        &dict_items,
+        &punct_items,
+        &connector,
+        &initial,
+        &settings,
+    )
+    .unwrap_or_else(exit_with_error);
+
+    // 报告各个最优布局，并完整分析第1名
+    let keys: String = settings
+        .opt_keys
+        .iter()
+        .filter(|c| !settings.opt_pinned_keys.contains(c))
+        .collect();
+    let mut report = vec![
+        format!("迭代次数\t{}", settings.opt_iterations),
+        format!("随机种子\t{}", settings.opt_seed),
+        format!("编码按键\t{keys}"),
+    ];
+    for (i, (key_map, time)) in results.iter().enumerate() {
+        let targets: String = keys
+            .chars()
+            .map(|c| key_map::map_key(c, key_map, connector.chords()))
+            .collect();
+        report.push(format!(
+            "第{}名\t当量\t{time:.1}\t字均当量\t{:.4}\t物理按键\t{targets}",
+            i + 1,
+            time / text.len() as f64
+        ));
+    }
+    let (best_map, _) = &results[0];
+    let (route, time) = layout_optimizer::encode_with_key_map(
+        &text,
+        &dict_items,
+        &punct_items,
+        &connector,
+        best_map,
+    )
+    .unwrap_or_else(exit_with_error);
+    report.push("---以下为第1名的完整分析结果---".to_string());
+    let analysis = code_analyzer::analyze(
+        &layout,
+        text.len(),
+        route,
+        best_map,
+        connector.chords(),
+        time,
+    );
+    report.extend(analysis.into_iter().skip(2));
+    report_saver::save(&text_path, "布局优化报告", report);
+
+    let mut best_lines: Vec<String> = best_map
+        .iter()
+        .map(|(from, to)| match (from, to) {
+            (' ', to) => format!("空格\t{to}"),
+            (from, ' ') => format!("{from}\t空格"),
+            (from, to) => format!("{from}\t{to}"),
+        })
+        .collect();
+    best_lines.sort();
+    report_saver::save(&text_path, "最优按键映射", best_lines);
+}
+
+/// 在全码词库的基础上分配简码，保存报告和加入简码后的词库
+fn optimize_short_codes(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let key_map = console_reader::get_key_map(&settings);
+    let connector =
+        console_reader::get_connector(time_map, chords, settings.clone(), layout, key_map);
+    let (dict_items, punct_items) = console_reader::get_dict_items(&punct_items, &settings);
+    let text_path = console_reader::get_text_path();
+    let (text, _) = text_encoder::read_text(&text_path, &settings).unwrap_or_else(exit_with_error);
+
+    let (initial, time, short_codes) =
+        short_code_optimizer::optimize(&text, &dict_items, &punct_items, &connector, &settings)
+            .unwrap_or_else(exit_with_error);
+
+    // 报告每个简码的收益
+    let chords = connector.chords();
+    let [one, two, three] = settings.short_slots;
+    let mut report = vec![
+        format!("初始总当量\t{initial:.1}"),
+        format!("优化后总当量\t{time:.1}"),
+        format!("减少当量\t{:.1}", initial - time),
+        format!("字均当量\t{:.4}", time / text.len() as f64),
+        format!("简码数量\t{}\t上限\t{one} {two} {three}", short_codes.len()),
+        "简码\t词组\t原编码\t收益".to_string(),
+    ];
+    for (short_code, word, old_code, gain) in &short_codes {
+        report.push(format!(
+            "{}\t{word}\t{}\t{gain:.1}",
+            chords.display(short_code),
+            chords.display(old_code)
+        ));
+    }
+    report_saver::save(&text_path, "简码优化报告", report);
+
+    // 简码条目在前，其后为原词库条目
+    let mut dict_lines: Vec<String> = short_codes
+        .iter()
+        .map(|(short_code, word, _, _)| format!("{word}\t{}", chords.display(short_code)))
+        .collect();
+    dict_lines.extend(
+        dict_items
+            .iter()
+            .map(|(word, code, priority)| match priority {
+                0 => format!("{word}\t{code}"),
+                p => format!("{word}\t{code}\t{p}"),
+            }),
+    );
+    report_saver::save(&text_path, "简码词库", dict_lines);
+}
+
+/// 列出一段短文本当量最小的若干条编码路径，看最优路径是否明显优于其他选择
+fn list_alternatives(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let key_map = console_reader::get_key_map(&settings);
+    let mut connector =
+        console_reader::get_connector(time_map, chords, settings.clone(), layout, key_map);
+    let (dict, _) = console_reader::get_dict(punct_items, connector.clone());
+    let text_path = console_reader::get_text_path();
+    let (text, _) = text_encoder::read_text(&text_path, &settings).unwrap_or_else(exit_with_error);
+
+    println!("共{}字。计算候选路径...", text.len());
+    let candidates = k_best_encoder::encode(&text, &dict, &mut connector, &settings)
+        .unwrap_or_else(exit_with_error);
+    println!("计算完成。共{}条候选路径。", candidates.len());
+
+    // 每条路径报告当量、与最优路径的差距、编码和分词
+    let best = candidates[0].1;
+    let mut report = vec![
+        format!("字数\t{}", text.len()),
+        format!("候选路径数\t{}", candidates.len()),
+    ];
+    for (i, (keys, time, lens)) in candidates.iter().enumerate() {
+        let mut start = 0;
+        let words: Vec<String> = lens
+            .iter()
+            .map(|len| {
+                start += len;
+                text[start - len..start].iter().collect()
+            })
+            .collect();
+        report.push(format!(
+            "第{}名\t当量\t{time:.2}\t比最优多\t{:.2}\t{:.2}%",
+            i + 1,
+            time - best,
+            100.0 * (time - best) / best.max(f64::MIN_POSITIVE)
+        ));
+        report.push(format!(
+            "编码\t{}",
+            connector.chords().display(keys).replace('\n', "\\n")
+        ));
+        report.push(format!("分词\t{}", words.join("/").replace('\n', "\\n")));
+    }
+    report_saver::save(&text_path, "候选路径报告", report);
+}
+
+/// 模拟逐词决定、打出后不再修改的打字者，与全局最优的编码对比
+fn simulate_typist(settings: Settings, layout: Layout, chords: Chords) {
+    // 模拟打字不直接键入西文，全局最优也不直接键入，使两者打的是同样的内容
+    let settings = Settings {
+        latin_direct: false,
+        ..settings
+    };
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let key_map = console_reader::get_key_map(&settings);
+    let connector = console_reader::get_connector(
+        time_map,
+        chords,
+        settings.clone(),
+        layout.clone(),
+        key_map.clone(),
+    );
+    let (dict, max_word_len) = console_reader::get_dict(punct_items, connector.clone());
+    let text_path = console_reader::get_text_path();
+    let (text, _) = text_encoder::read_text(&text_path, &settings).unwrap_or_else(exit_with_error);
+
+    // 全局最优
+    let best_time =
+        text_encoder::encode(&text, &dict, connector.clone(), 16.max(max_word_len), true)
+            .unwrap_or_else(exit_with_error)
+            .time;
+
+    // 模拟打字
+    let strategy = match settings.typist_lookahead {
+        0 => "最长匹配".to_string(),
+        n => format!("前瞻{n}字"),
+    };
+    println!("模拟打字（{strategy}）...");
+    let mut typist_connector = connector;
+    let (route, time) = typist_simulator::simulate(
+        &text,
+        &dict,
+        &mut typist_connector,
+        settings.typist_lookahead,
+    );
+
+    let text_len = text.len() as f64;
+    let mut report = vec![
+        format!("打字策略\t{strategy}"),
+        format!(
+            "最优当量\t{best_time:.1}\t字均当量\t{:.4}",
+            best_time / text_len
+        ),
+        format!("模拟当量\t{time:.1}\t字均当量\t{:.4}", time / text_len),
+        format!(
+            "模拟比最优多\t{:.1}\t{:.2}%",
+            time - best_time,
+            100.0 * (time - best_time) / best_time.max(f64::MIN_POSITIVE)
+        ),
+        "---以下为模拟打字的完整分析结果---".to_string(),
+    ];
+    let chords = typist_connector.chords();
+    let analysis = code_analyzer::analyze(&layout, text.len(), route, &key_map, chords, time);
+    report.extend(analysis.into_iter().skip(2));
+    report_saver::save(&text_path, "模拟打字报告", report);
+}
+
+/// 并行编码语料库中的各文件，保存逐文件的结果和加权汇总的报告
+fn encode_corpus(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let key_map = console_reader::get_key_map(&settings);
+    let connector =
+        console_reader::get_connector(time_map, chords, settings.clone(), layout, key_map);
+    let (dict, max_word_len) = console_reader::get_dict(punct_items, connector.clone());
+    let (root, files) = console_reader::get_corpus_files();
+    let weights = console_reader::get_corpus_weights(&settings);
+
+    println!("共{}个文件。并行计算编码...", files.len());
+    let (results, connector) =
+        corpus_encoder::encode(&files, &dict, &connector, &settings, 16.max(max_word_len));
+    println!("计算完成。");
+
+    let report = corpus_encoder::report(&root, &files, &results, &weights);
+    report_saver::save(&root, "语料库编码报告", report);
+    connector.report_script_errors();
+    let unknown_count = connector.unknown_keys_count();
+    if unknown_count > 0 && console_reader::need_to_report_unknown_keys(unknown_count) {
+        connector.report_unknown_keys(&root);
+    }
+}
+
+/// 对词库、连接方法、文本的每种组合计算最小当量编码，把各项分析指标汇总为一张对比表
+fn compare_batch(settings: Settings, layout: Layout, chords: Chords) {
+    let punct_items = config_loader::load_punct_items(&settings).unwrap_or_else(exit_with_error);
+    let time_map = config_loader::load_time_map(&settings, &chords).unwrap_or_else(exit_with_error);
+
+    let key_map = console_reader::get_key_map(&settings);
+    let dict_paths = console_reader::get_paths("词库");
+    let (methods, script) = console_reader::get_methods(&settings, &chords);
+    let text_paths = console_reader::get_paths("待编码文本");
+    if dict_paths.is_empty() || text_paths.is_empty() {
+        exit_with_error::<()>("没有输入词库或待编码文本");
+    }
+    let texts: Vec<_> = text_paths
+        .iter()
+        .map(|path| text_encoder::read_text(path, &settings).map(|(text, _)| text))
+        .collect();
+
+    let mut runs = Vec::new();
+    let mut results = Vec::new();
+    for dict_path in &dict_paths {
+        for &method in &methods {
+            println!("词库{}，连接方法{method}：", dict_path.display());
+            let connector = RouteConnector::new(
+                time_map.clone(),
+                chords.clone(),
+                settings.clone(),
+                layout.clone(),
+                key_map.clone(),
+                method,
+                script.clone(),
+            );
+            let dict = dict_loader::load_dict(dict_path, punct_items.clone(), connector.clone());
+            for (text_path, text) in text_paths.iter().zip(&texts) {
+                runs.push((dict_path.as_path(), method, text_path.as_path()));
+                let (dict, max_word_len) = match &dict {
+                    Ok(dict) => dict,
+                    Err(message) => {
+                        results.push(Err(message.to_string()));
+                        continue;
+                    }
+                };
+                let text = match text {
+                    Ok(text) => text,
+                    Err(message) => {
+                        results.push(Err(message.to_string()));
+                        continue;
+                    }
+                };
+                println!("计算{}的编码...", text_path.display());
+                let buffer_size = 16.max(*max_word_len);
+                let result =
+                    text_encoder::encode(text, dict, connector.clone(), buffer_size, false)
+                        .map(|encoded| {
+                            let key_count = encoded.route.len();
+                            let objective = code_analyzer::analyze_objective(
+                                &settings,
+                                encoded.time,
+                                key_count,
+                                &encoded.segments,
+                            );
+                            // 跳过编码路径本身，只留分析指标
+                            let mut analysis: Vec<String> = code_analyzer::analyze(
+                                &layout,
+                                text.len(),
+                                encoded.route,
+                                &key_map,
+                                encoded.connector.chords(),
+                                encoded.time,
+                            )
+                            .into_iter()
+                            .skip(2)
+                            .collect();
+                            analysis.extend(objective.into_iter().take(2)); // 选重次数和翻页次数
+                            analysis
+                        })
+                        .map_err(str::to_string);
+                results.push(result);
+            }
+        }
+    }
+
+    println!("计算完成。共{}种组合。", runs.len());
+    let table = batch_comparer::compare(&runs, &results);
+    report_saver::save(&text_paths[0], "批量对比报告", table);
 }
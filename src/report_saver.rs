@@ -1,9 +1,9 @@
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::Path;
 
 fn save_to_file(
-    text_path: &PathBuf,
+    text_path: &Path,
     name: &str,
     content: &Vec<String>,
 ) -> Result<String, &'static str> {
@@ -34,7 +34,7 @@ fn save_to_file(
     Ok(unique_path)
 }
 
-pub(crate) fn save(text_path: &PathBuf, name: &str, content: Vec<String>) {
+pub(crate) fn save(text_path: &Path, name: &str, content: Vec<String>) {
     println!("保存{name}...");
     match save_to_file(text_path, name, &content) {
         Ok(unique_path) => {
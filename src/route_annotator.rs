@@ -0,0 +1,54 @@
+use crate::chord::Chords;
+use crate::route_buffer::Segment;
+
+/// 文本中不便显示的字符
+fn show_text(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        c => c.to_string(),
+    }
+}
+
+/// 按键中的空格写作`_`，其余不便显示的字符同文本
+fn show_keys(keys: &[char], chords: &Chords) -> String {
+    chords
+        .display(keys)
+        .chars()
+        .map(|c| match c {
+            ' ' => "_".to_string(),
+            c => show_text(c),
+        })
+        .collect()
+}
+
+/// 逐段列出文本和所用的按键，每行格式为`文本|按键|当量`，按键中的空格写作`_`。
+/// 当量为连接这一段所增加的当量。连接器在两段之间增删的按键计入前一段。
+/// range为从0开始的字序号范围，只列出起点在其中的段
+pub(crate) fn annotate(
+    text: &[char],
+    route: &[char],
+    segments: &[Segment],
+    chords: &Chords,
+    range: (usize, usize),
+) -> Vec<String> {
+    let mut lines = Vec::with_capacity(segments.len());
+    let mut prev_time = 0.0;
+    for (i, segment) in segments.iter().enumerate() {
+        let time = ((segment.time - prev_time) * 100.0).round() / 100.0 + 0.0; // 加0.0避免输出-0.00
+        prev_time = segment.time;
+        if segment.start < range.0 || segment.start >= range.1 {
+            continue;
+        }
+
+        let word: String = text[segment.start..segment.start + segment.len]
+            .iter()
+            .map(|c| show_text(*c))
+            .collect();
+        let key_end = segments.get(i + 1).map_or(route.len(), |s| s.key_start);
+        let keys = &route[segment.key_start..key_end];
+        lines.push(format!("{word}|{}|{time:.2}", show_keys(keys, chords)));
+    }
+    lines
+}
@@ -1,11 +1,73 @@
+use crate::chord::Chords;
+use crate::dict_loader::Picks;
+use crate::latin_typer::Piece;
 use crate::route_connector::RouteConnector;
-use std::path::PathBuf;
+use crate::settings::Settings;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// 当量由两位小数逐段累加而来，累加的起点不同会有微小的浮点误差。相差在此范围内视为相等
+const EPSILON: f64 = 1e-6;
+
+/// 比较两个累加的当量或目标值
+pub(crate) fn compare(a: f64, b: f64) -> Ordering {
+    match a - b {
+        d if d.abs() <= EPSILON => Ordering::Equal,
+        d if d < 0.0 => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+/// 编码路径中的一段：一个词组或单字，及其编码
+#[derive(Clone)]
+pub(crate) struct Segment {
+    /// 在文本中的起始位置
+    pub(crate) start: usize,
+    /// 字数
+    pub(crate) len: usize,
+    /// 词库中的编码。词库中没有的字符，编码为其本身
+    pub(crate) code: Vec<char>,
+    /// 是否来自词库
+    pub(crate) in_dict: bool,
+    /// 是否为切换到西文状态直接键入的西文
+    pub(crate) latin: bool,
+    /// 编码中生成的选重键和翻页键的个数
+    pub(crate) picks: Picks,
+    /// 本段的按键在全文编码中的起始位置。连接器在两段之间增删的按键计入前一段
+    pub(crate) key_start: usize,
+    /// 连接本段后的累计当量
+    pub(crate) time: f64,
+}
+
+/// 路径上的各段，以链表存储，使克隆路径时不必复制各段
+struct SegmentNode {
+    segment: Segment,
+    prev: Option<Arc<SegmentNode>>,
+}
+
+impl Drop for SegmentNode {
+    /// 逐个释放，避免长链表递归释放时栈溢出
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(node) = prev {
+            prev = match Arc::try_unwrap(node) {
+                Ok(mut node) => node.prev.take(),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+/// 一个位置上的最优路径：(编码路径, 当量, 目标值, 路径上的最后一段)
+type Slot = (Vec<char>, f64, f64, Option<Arc<SegmentNode>>);
 
 pub(crate) struct RouteBuffer {
     /// 索引为待编码的第一个字符的位置
-    buffer: Vec<(Vec<char>, f64)>,
+    buffer: Vec<Slot>,
     /// 编码路径连接器
     connector: RouteConnector,
+    /// 目标值中当量、码数、选重次数、翻页次数的权重
+    weights: [f64; 4],
     /// 当前位置
     head: usize,
     /// 字数计数器
@@ -23,9 +85,17 @@ impl RouteBuffer {
         if size == 0 {
             Err("编码路径缓冲区大小不能为0")
         } else {
+            let settings = connector.settings();
+            let weights = [
+                settings.obj_time_weight,
+                settings.obj_key_weight,
+                settings.obj_selection_weight,
+                settings.obj_page_weight,
+            ];
             Ok(Self {
-                buffer: vec![(Vec::new(), 0.0); size],
+                buffer: vec![(Vec::new(), 0.0, 0.0, None); size],
                 connector,
+                weights,
                 head: 0,
                 count: 0,
                 distance: 0,
@@ -35,14 +105,37 @@ impl RouteBuffer {
         }
     }
 
+    /// 以上文末尾的按键作为起点，用于分段编码。须在编码前调用
+    pub(crate) fn seed(&mut self, keys: &[char]) {
+        self.buffer[self.head].0 = keys.to_vec();
+    }
+
+    /// 缓冲区大小。连接的编码所跨的字数须小于此值
+    pub(crate) fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub(crate) fn settings(&self) -> &Settings {
+        self.connector.settings()
+    }
+
+    pub(crate) fn chords(&self) -> &Chords {
+        self.connector.chords()
+    }
+
+    /// 取出连接器，其中记录了找不到当量的按键组合
+    pub(crate) fn into_connector(self) -> RouteConnector {
+        self.connector
+    }
+
     /// 获取是否在当前位置连接过编码
     pub(crate) fn is_connected(&self) -> bool {
         self.connected
     }
 
-    /// 获取迭代过的字数
-    pub(crate) fn count(&self) -> usize {
-        self.count
+    /// 获取是否有路径到达当前位置。位于词组中间而无法到达的位置，不能作为编码的起点
+    pub(crate) fn is_reachable(&self) -> bool {
+        self.count == 0 || self.buffer[self.head].3.is_some()
     }
 
     /// 找不到当量的按键组合数量
@@ -50,22 +143,43 @@ impl RouteBuffer {
         self.connector.unknown_keys_count()
     }
 
-    /// 导出找不到当量的按键组合
-    pub(crate) fn report_unknown_keys(&self, text_path: &PathBuf) {
-        self.connector.report_unknown_keys(text_path);
-    }
-
     pub(crate) fn next(&mut self) {
         self.buffer[self.head].0.clear();
         self.buffer[self.head].1 = 0.0;
+        self.buffer[self.head].2 = 0.0;
+        self.buffer[self.head].3 = None;
         self.head = (self.head + 1) % self.buffer.len();
         self.count += 1;
         self.distance -= 1;
         self.connected = false;
     }
 
-    /// 在当前位置连接编码
-    pub(crate) fn connect_code(&mut self, word_len: usize, tail_code: &[char], tail_time: f64) {
+    /// 在当前位置连接编码。picks为词库条目中的选重和翻页次数，为None表示词库中没有此字符，以原字符作为编码
+    pub(crate) fn connect_code(
+        &mut self,
+        word_len: usize,
+        tail_code: &[char],
+        tail_time: f64,
+        picks: Option<Picks>,
+    ) {
+        self.link(word_len, tail_code, tail_time, picks, None);
+    }
+
+    /// 在当前位置连接直接键入的一段西文
+    pub(crate) fn connect_latin(&mut self, piece: &Piece) {
+        let time = self.connector.get_time(&piece.keys) + piece.switch_time;
+        self.link(piece.len, &piece.keys, time, None, Some(piece.continued));
+    }
+
+    /// 连接编码并更新目标位置的最优路径。latin为Some时是直接键入的西文，其值为是否接续上一段
+    fn link(
+        &mut self,
+        word_len: usize,
+        tail_code: &[char],
+        tail_time: f64,
+        picks: Option<Picks>,
+        latin: Option<bool>,
+    ) {
         // 如果当前路径太长，且当前路径为唯一路径（全局最优），则暂存并清空缓冲区
         if self.buffer[self.head].0.len() > 100 && self.distance == 0 {
             // 分成已经不影响后续编码的头部和仍在影响后续编码的尾部
@@ -76,20 +190,60 @@ impl RouteBuffer {
         }
 
         // 连接编码
-        let (code, time) = self.connector.connect(
-            &self.buffer[self.head].0,
-            tail_code,
-            self.buffer[self.head].1,
-            tail_time,
-        );
-
-        // 若目标位置没有编码，或当量更小，或同当量且编码更短：更新最优路径
+        let (head_route, head_time, head_score, head_node) = &self.buffer[self.head];
+        let (code, time) = match latin {
+            Some(true) => self
+                .connector
+                .append(head_route, tail_code, *head_time, tail_time),
+            _ => self
+                .connector
+                .connect(head_route, tail_code, *head_time, tail_time),
+        };
+
+        // 目标值为当量、码数、选重次数、翻页次数的加权和，按增量累加
+        let (selections, pages) = picks.unwrap_or((0, 0));
+        let [time_weight, key_weight, selection_weight, page_weight] = self.weights;
+        let score = head_score
+            + time_weight * (time - head_time)
+            + key_weight * (code.len() as f64 - head_route.len() as f64)
+            + selection_weight * selections as f64
+            + page_weight * pages as f64;
+
+        // 若目标位置没有编码，或目标值更小，或同目标值而当量更小，或再同当量且编码更短：更新最优路径
         let index = (self.head + word_len) % self.buffer.len();
-        if self.buffer[index].0.is_empty()
-            || time < self.buffer[index].1
-            || code.len() < self.buffer[index].0.len()
+        let (old_route, old_time, old_score, old_node) = &self.buffer[index];
+        if old_node.is_none()
+            || compare(score, *old_score)
+                .then(compare(time, *old_time))
+                .then(code.len().cmp(&old_route.len()))
+                .is_lt()
         {
-            self.buffer[index] = (code, time);
+            // 连接器只改动上文的末尾。新的一段从改动处之后新编码出现的位置开始，
+            // 找不到新编码（如被脚本改写）则从改动处开始
+            let unchanged = head_route
+                .iter()
+                .zip(&code)
+                .take_while(|(c1, c2)| c1 == c2)
+                .count();
+            let key_start = code[unchanged..]
+                .windows(tail_code.len().max(1))
+                .position(|w| w == tail_code)
+                .map_or(unchanged, |i| unchanged + i);
+            let segment = Segment {
+                start: self.count,
+                len: word_len,
+                code: tail_code.to_vec(),
+                in_dict: picks.is_some(),
+                latin: latin.is_some(),
+                picks: (selections, pages),
+                key_start: self.global_best_route.len() + key_start,
+                time,
+            };
+            let node = SegmentNode {
+                segment,
+                prev: head_node.clone(),
+            };
+            self.buffer[index] = (code, time, score, Some(Arc::new(node)));
         }
 
         // 更新状态
@@ -106,4 +260,28 @@ impl RouteBuffer {
             Ok((self.global_best_route.clone(), self.buffer[self.head].1))
         }
     }
+
+    /// 获取全局最优路径上的各段，按文本顺序排列。须在编码完成后调用，再用align_segments对齐按键
+    pub(crate) fn get_segments(&self) -> Vec<Segment> {
+        let mut segments = Vec::with_capacity(self.count);
+        let mut node = self.buffer[self.head].3.as_deref();
+        while let Some(n) = node {
+            segments.push(n.segment.clone());
+            node = n.prev.as_deref();
+        }
+        segments.reverse();
+        segments
+    }
+}
+
+/// 对齐各段的按键起点：后面的段改动了前面的段的末尾时，前面的段让出被改动的按键；第一段从头开始
+pub(crate) fn align_segments(segments: &mut [Segment]) {
+    let mut next_start = usize::MAX;
+    for segment in segments.iter_mut().rev() {
+        segment.key_start = segment.key_start.min(next_start);
+        next_start = segment.key_start;
+    }
+    if let Some(first) = segments.first_mut() {
+        first.key_start = 0;
+    }
 }
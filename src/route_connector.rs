@@ -1,29 +1,113 @@
+use crate::chord::Chords;
+use crate::connector_script::{ConnectorScript, TAIL_LEN};
+use crate::key_map::{self, KeyMap};
+use crate::layout::Layout;
+use crate::settings::Settings;
+use crate::time_map::TimeMap;
+use crate::time_map_generator;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::Path;
 
 pub(crate) struct RouteConnector {
-    /// 按键之间的用时当量：键为两个按键对应的字符，值为用时当量
-    time_map: HashMap<(char, char), f64>,
+    /// 按键及并击的用时当量
+    time_map: TimeMap,
+    /// 并击表，编码中的并击都登记在这里
+    chords: Chords,
+    /// 修饰键、修改键等的当量设置
+    settings: Settings,
+    /// 键盘布局，用于估算找不到的当量
+    layout: Layout,
+    /// 编码中的按键到物理按键的映射。查找当量前先映射，连接规则仍按编码中的按键判断
+    key_map: KeyMap,
+    /// 按键及并击映射后的物理按键。构造时预先映射按键映射中的按键和已登记的并击，
+    /// 之后登记的并击和不需映射的按键在首次用到时补上，此后只需查表
+    physical_keys: HashMap<char, char>,
     /// 找不到当量的按键组合
     unknown_keys: HashSet<(char, char)>,
-    /// 连接方法代号：0-空格或符号，1-无间隔，2-键道顶功
+    /// 连接方法代号：0-空格或符号，1-无间隔，2-键道顶功，3-并击，4-脚本
     method_code: usize,
+    /// 连接方法为4时使用的连接脚本
+    script: Option<ConnectorScript>,
+    /// 连接脚本执行出错的次数和首个错误信息。出错处按方法0连接
+    script_errors: (usize, Option<String>),
 }
 
 impl RouteConnector {
-    pub(crate) fn new(time_map: HashMap<(char, char), f64>, method_code: usize) -> Self {
+    pub(crate) fn new(
+        time_map: TimeMap,
+        chords: Chords,
+        settings: Settings,
+        layout: Layout,
+        key_map: KeyMap,
+        method_code: usize,
+        script: Option<ConnectorScript>,
+    ) -> Self {
         Self {
             time_map,
+            physical_keys: key_map::precompute(&key_map, &chords),
+            chords,
+            settings,
+            layout,
+            key_map,
             unknown_keys: HashSet::new(),
             method_code,
+            script,
+            script_errors: (0, None),
         }
     }
 
     pub(crate) fn clone(&self) -> Self {
         Self {
             time_map: self.time_map.clone(),
+            chords: self.chords.clone(),
+            settings: self.settings.clone(),
+            layout: self.layout.clone(),
+            key_map: self.key_map.clone(),
+            physical_keys: self.physical_keys.clone(),
             unknown_keys: self.unknown_keys.clone(),
             method_code: self.method_code,
+            script: self.script.clone(),
+            script_errors: self.script_errors.clone(),
+        }
+    }
+
+    /// 克隆一个使用另一种按键映射的连接器，不带已记录的找不到当量的按键组合
+    pub(crate) fn with_key_map(&self, key_map: KeyMap) -> Self {
+        Self {
+            time_map: self.time_map.clone(),
+            chords: self.chords.clone(),
+            settings: self.settings.clone(),
+            layout: self.layout.clone(),
+            physical_keys: key_map::precompute(&key_map, &self.chords),
+            key_map,
+            unknown_keys: HashSet::new(),
+            method_code: self.method_code,
+            script: self.script.clone(),
+            script_errors: (0, None),
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub(crate) fn chords(&self) -> &Chords {
+        &self.chords
+    }
+
+    /// 并入另一个连接器记录的找不到当量的按键组合和连接脚本的错误
+    pub(crate) fn merge_records(&mut self, other: &RouteConnector) {
+        self.unknown_keys.extend(other.unknown_keys.iter().copied());
+        self.script_errors.0 += other.script_errors.0;
+        if self.script_errors.1.is_none() {
+            self.script_errors.1.clone_from(&other.script_errors.1);
+        }
+    }
+
+    /// 连接脚本出过错时，在控制台报告出错次数和首个错误信息
+    pub(crate) fn report_script_errors(&self) {
+        if let (count, Some(message)) = &self.script_errors {
+            println!("连接脚本共执行出错{count}次，出错处已按方法0连接。首个错误信息：{message}");
         }
     }
 
@@ -31,30 +115,125 @@ impl RouteConnector {
         self.unknown_keys.len()
     }
 
-    pub(crate) fn report_unknown_keys(&self, text_path: &PathBuf) {
+    pub(crate) fn report_unknown_keys(&self, text_path: &Path) {
         let content = self
             .unknown_keys
             .iter()
-            .map(|(c1, c2)| format!("{c1}{c2}"))
+            .map(|(c1, c2)| self.chords.display(&[*c1, *c2]))
             .collect();
         crate::report_saver::save(text_path, "找不到当量的按键组合", content);
     }
 
+    /// 一段编码的用时当量：并击本身的当量，加上相邻按键之间的当量，再加上三键组合的修正值
     pub(crate) fn get_time(&mut self, chars: &[char]) -> f64 {
+        let chord_sum: f64 = chars.iter().map(|c| self.get_chord_time(*c)).sum();
+        let sum = chord_sum + self.get_join_time(chars) + self.get_triple_time(chars);
+        (sum * 100.0).round() / 100.0 // 避免浮点数精度问题
+    }
+
+    /// 只计算相邻按键之间的当量，用于连接编码，避免重复计入并击本身的当量
+    fn get_join_time(&mut self, chars: &[char]) -> f64 {
         let mut sum = 0.0;
-        for i in 0..chars.len() - 1 {
-            let key = (chars[i], chars[i + 1]);
-            match self.time_map.get(&key) {
+        for i in 0..chars.len().saturating_sub(1) {
+            let key = (self.physical_key(chars[i]), self.physical_key(chars[i + 1]));
+            match self.get_pair_time(key) {
                 Some(value) => sum += value,
                 None => {
                     self.unknown_keys.insert(key);
-                    sum += 1.5;
+                    sum += self.get_fallback_time(key);
                 }
             }
         }
         (sum * 100.0).round() / 100.0 // 避免浮点数精度问题
     }
 
+    /// 三键组合的修正值之和。表中没有的组合不修正
+    fn get_triple_time(&mut self, chars: &[char]) -> f64 {
+        if self.time_map.triples.is_empty() {
+            return 0.0;
+        }
+        let physical: Vec<char> = chars.iter().map(|c| self.physical_key(*c)).collect();
+        physical
+            .windows(3)
+            .filter_map(|w| self.time_map.triples.get(&(w[0], w[1], w[2])))
+            .sum()
+    }
+
+    /// 并击本身的当量。表中没有时，每个修饰键计入修饰键当量；单键为0
+    fn get_chord_time(&mut self, c: char) -> f64 {
+        let c = self.physical_key(c);
+        if let Some(value) = self.time_map.chords.get(&c) {
+            return *value;
+        }
+        match self.chords.keys_of(c) {
+            Some(keys) => {
+                let modifier_count = keys.iter().filter(|k| self.chords.is_modifier(**k)).count();
+                modifier_count as f64 * self.settings.modifier_time
+            }
+            None => 0.0,
+        }
+    }
+
+    /// 编码中的按键或并击所对应的物理按键
+    fn physical_key(&mut self, c: char) -> char {
+        if self.key_map.is_empty() {
+            return c;
+        }
+        if let Some(mapped) = self.physical_keys.get(&c) {
+            return *mapped;
+        }
+        let mapped = key_map::map_key(c, &self.key_map, &self.chords);
+        self.physical_keys.insert(c, mapped);
+        mapped
+    }
+
+    /// 查找两个按键之间的当量。表中没有时：涉及修改键，取修改键当量；
+    /// 涉及并击，取组成并击的各键（修饰键由另一只手按住，不计）之间当量的最大值
+    fn get_pair_time(&self, key: (char, char)) -> Option<f64> {
+        if let Some(value) = self.time_map.pairs.get(&key) {
+            return Some(*value);
+        }
+        let correction_keys = &self.settings.correction_keys;
+        if correction_keys.contains(&key.0) || correction_keys.contains(&key.1) {
+            return Some(self.settings.correction_time);
+        }
+        if !self.chords.is_chord(key.0) && !self.chords.is_chord(key.1) {
+            return None;
+        }
+        let (k1, k2) = (
+            self.chords.moving_keys(key.0),
+            self.chords.moving_keys(key.1),
+        );
+        k1.iter()
+            .flat_map(|c1| k2.iter().map(move |c2| (*c1, *c2)))
+            .filter_map(|pair| self.time_map.pairs.get(&pair))
+            .copied()
+            .reduce(f64::max)
+    }
+
+    /// 找不到当量时计入的当量：按设置用布局估算，估算不了则取设置的未知当量
+    fn get_fallback_time(&self, key: (char, char)) -> f64 {
+        if !self.settings.estimate_unknown {
+            return self.settings.unknown_time;
+        }
+        let (k1, k2) = (
+            self.chords.moving_keys(key.0),
+            self.chords.moving_keys(key.1),
+        );
+        k1.iter()
+            .flat_map(|c1| k2.iter().map(move |c2| (*c1, *c2)))
+            .map(|(c1, c2)| self.estimate_time(c1, c2))
+            .collect::<Option<Vec<f64>>>()
+            .and_then(|times| times.into_iter().reduce(f64::max))
+            .unwrap_or(self.settings.unknown_time)
+    }
+
+    /// 按生成当量表的几何模型估算两键的当量。任一键不在布局中则无法估算
+    fn estimate_time(&self, c1: char, c2: char) -> Option<f64> {
+        let (i1, i2) = (self.layout.get(c1)?, self.layout.get(c2)?);
+        Some(time_map_generator::estimate(&self.settings, c1, i1, c2, i2))
+    }
+
     pub(crate) fn connect(
         &mut self,
         s1: &[char],
@@ -62,19 +241,58 @@ impl RouteConnector {
         t1: f64,
         t2: f64,
     ) -> (Vec<char>, f64) {
+        let (s, t) = self.join(s1, s2, t1, t2);
+        if self.method_code == 4 {
+            return (s, t); // 脚本按连接前后的当量差计算，已包含三键修正
+        }
+        self.add_triple_delta(s1, s2, s, t)
+    }
+
+    /// 不按连接方法，直接在上文后接续编码，用于直接键入的西文
+    pub(crate) fn append(
+        &mut self,
+        s1: &[char],
+        s2: &[char],
+        t1: f64,
+        t2: f64,
+    ) -> (Vec<char>, f64) {
+        let t = match (s1.last(), s2.first()) {
+            (Some(c1), Some(c2)) => t1 + t2 + self.get_join_time(&[*c1, *c2]),
+            _ => t1 + t2,
+        };
+        let mut s = s1.to_vec();
+        s.extend_from_slice(s2);
+        self.add_triple_delta(s1, s2, s, t)
+    }
+
+    /// 计入连接后新形成和被拆散的三键组合的修正值
+    fn add_triple_delta(
+        &mut self,
+        s1: &[char],
+        s2: &[char],
+        s: Vec<char>,
+        t: f64,
+    ) -> (Vec<char>, f64) {
+        if self.time_map.triples.is_empty() {
+            return (s, t);
+        }
+
+        // 连接只改动上文的末键，所以上文末尾几个键之前的部分不变。
+        // 三键修正的差值只需在这之后的部分计算：新形成的组合计入，被拆散的组合扣除
+        let start = s1.len().saturating_sub(TAIL_LEN);
+        let delta = self.get_triple_time(&s[start..])
+            - self.get_triple_time(&s1[start..])
+            - self.get_triple_time(s2);
+        (s, t + delta)
+    }
+
+    /// 按连接方法连接两段编码，只计算两两按键之间的当量
+    fn join(&mut self, s1: &[char], s2: &[char], t1: f64, t2: f64) -> (Vec<char>, f64) {
         // 取出前部的末字符、后部的首字符、后部的末字符
         let s1_last = s1.last().unwrap_or(&'\0');
         let s2_first = s2.first().unwrap_or(&'\0');
         let s2_last = s2.last().unwrap_or(&'\0');
 
-        fn is_letter(c: char) -> bool {
-            "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".contains(c)
-        }
-
-        fn is_number(c: char) -> bool {
-            "0123456789".contains(c)
-        }
-
         // 键道形码码元
         fn is_xing(c: char) -> bool {
             "aiouvAIOUV".contains(c)
@@ -86,31 +304,13 @@ impl RouteConnector {
         }
 
         match self.method_code {
-            0 => {
-                // 字母 + (字母或数字)，则加空格
-                if s1.is_empty() {
-                    (s2.to_vec(), t2)
-                } else if is_letter(*s1_last) && (is_letter(*s2_first) || is_number(*s2_first)) {
-                    let mid = vec![*s1_last, ' ', *s2_first];
-                    let t = t1 + t2 + self.get_time(&mid);
-                    let mut s = s1.to_vec();
-                    s.push(' ');
-                    s.append(&mut s2.to_vec());
-                    (s, t)
-                } else {
-                    let mid = vec![*s1_last, *s2_first];
-                    let t = t1 + t2 + self.get_time(&mid);
-                    let mut s = s1.to_vec();
-                    s.append(&mut s2.to_vec());
-                    (s, t)
-                }
-            }
+            0 => self.join_with_space(s1, s2, t1, t2),
             1 => {
                 if s1.is_empty() {
                     (s2.to_vec(), t2)
                 } else {
                     let mid = vec![*s1_last, *s2_first];
-                    let t = t1 + t2 + self.get_time(&mid);
+                    let t = t1 + t2 + self.get_join_time(&mid);
                     let mut s = s1.to_vec();
                     s.append(&mut s2.to_vec());
                     (s, t)
@@ -123,7 +323,7 @@ impl RouteConnector {
                 // 新码以音码结尾，且不足4码：新码后补空格
                 if is_yin(*s2_last) && s2.len() < 4 {
                     mod_s2.push(' ');
-                    mod_t2 += self.get_time(&mod_s2[mod_s2.len() - 2..]);
+                    mod_t2 += self.get_join_time(&mod_s2[mod_s2.len() - 2..]);
                 }
                 // 没有上文：直接返回
                 if s1.is_empty() {
@@ -142,12 +342,12 @@ impl RouteConnector {
                     && !is_number(*s2_first)
                 {
                     mod_s1.pop().expect("无法删除头部末尾的空格");
-                    mod_t1 -= self.get_time(&s1[s1.len() - 2..]);
+                    mod_t1 -= self.get_join_time(&s1[s1.len() - 2..]);
                 }
                 // 上文末尾为字母，且新码以形码或数字开头：在上文末尾加空格
                 else if is_letter(*s1_last) && (is_xing(*s2_first) || is_number(*s2_first)) {
                     mod_s1.push(' ');
-                    mod_t1 += self.get_time(&mod_s1[mod_s1.len() - 2..]);
+                    mod_t1 += self.get_join_time(&mod_s1[mod_s1.len() - 2..]);
                 }
 
                 // 连接
@@ -155,11 +355,91 @@ impl RouteConnector {
                     *mod_s1.last().expect("无法获取头部末字符"),
                     *mod_s2.first().expect("无法获取尾部首字符"),
                 ];
-                let time = mod_t1 + mod_t2 + self.get_time(&mid);
+                let time = mod_t1 + mod_t2 + self.get_join_time(&mid);
                 mod_s1.append(&mut mod_s2);
                 (mod_s1, time)
             }
+            3 => {
+                // 以单键结尾的编码，后接字母、数字或并击开头的编码，则加空格；并击松开即上屏，无需间隔
+                if s1.is_empty() {
+                    (s2.to_vec(), t2)
+                } else if !self.chords.is_chord(*s1_last)
+                    && (is_letter(*s2_first)
+                        || is_number(*s2_first)
+                        || self.chords.is_chord(*s2_first))
+                {
+                    let mid = vec![*s1_last, ' ', *s2_first];
+                    let t = t1 + t2 + self.get_join_time(&mid);
+                    let mut s = s1.to_vec();
+                    s.push(' ');
+                    s.append(&mut s2.to_vec());
+                    (s, t)
+                } else {
+                    let mid = vec![*s1_last, *s2_first];
+                    let t = t1 + t2 + self.get_join_time(&mid);
+                    let mut s = s1.to_vec();
+                    s.append(&mut s2.to_vec());
+                    (s, t)
+                }
+            }
+            4 => {
+                // 上文末尾的几个键和新码交给脚本连接，按连接前后的当量差计入
+                let (head, tail) = s1.split_at(s1.len().saturating_sub(TAIL_LEN));
+                let tail_time = self.get_time(tail);
+                let joined = self.script.as_ref().expect("未加载连接脚本").join(
+                    &self.chords,
+                    tail,
+                    s2,
+                    tail_time,
+                    t2,
+                );
+                let (joined, extra) = match joined {
+                    Ok(joined) => joined,
+                    Err(message) => {
+                        if self.script_errors.1.is_none() {
+                            println!("连接脚本执行出错，出错处按方法0连接。错误信息：{message}");
+                            self.script_errors.1 = Some(message);
+                        }
+                        self.script_errors.0 += 1;
+                        return self.join_with_space(s1, s2, t1, t2);
+                    }
+                };
+                let time = t1 + t2 + self.get_time(&joined) - tail_time - self.get_time(s2) + extra;
+                let mut s = head.to_vec();
+                s.extend(joined);
+                (s, time)
+            }
             _ => panic!("未知的连接方法代号"),
         }
     }
+
+    /// 连接方法0：字母 + (字母或数字)，则加空格
+    fn join_with_space(&mut self, s1: &[char], s2: &[char], t1: f64, t2: f64) -> (Vec<char>, f64) {
+        let s1_last = s1.last().unwrap_or(&'\0');
+        let s2_first = s2.first().unwrap_or(&'\0');
+        if s1.is_empty() {
+            (s2.to_vec(), t2)
+        } else if is_letter(*s1_last) && (is_letter(*s2_first) || is_number(*s2_first)) {
+            let mid = vec![*s1_last, ' ', *s2_first];
+            let t = t1 + t2 + self.get_join_time(&mid);
+            let mut s = s1.to_vec();
+            s.push(' ');
+            s.append(&mut s2.to_vec());
+            (s, t)
+        } else {
+            let mid = vec![*s1_last, *s2_first];
+            let t = t1 + t2 + self.get_join_time(&mid);
+            let mut s = s1.to_vec();
+            s.append(&mut s2.to_vec());
+            (s, t)
+        }
+    }
+}
+
+fn is_letter(c: char) -> bool {
+    "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ".contains(c)
+}
+
+fn is_number(c: char) -> bool {
+    "0123456789".contains(c)
 }
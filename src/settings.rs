@@ -0,0 +1,303 @@
+use encoding_rs::Encoding;
+
+/// 可选的设置项。在config/settings.txt中每行定义一项，格式为`名称\t值`，用`#`号引导注释
+#[derive(Clone)]
+pub(crate) struct Settings {
+    /// 修饰键：按住后再按下一个键，与该键视为并击
+    pub(crate) modifiers: Vec<char>,
+    /// 含修饰键的并击在当量表中没有定义时，每个修饰键额外计入的当量
+    pub(crate) modifier_time: f64,
+    /// 退格等修改键
+    pub(crate) correction_keys: Vec<char>,
+    /// 修改键与其他键之间的当量在当量表中没有定义时，计入的当量
+    pub(crate) correction_time: f64,
+    /// 找不到当量的按键组合计入的当量
+    pub(crate) unknown_time: f64,
+    /// 是否按键盘布局估算找不到的当量。布局中没有的按键仍使用unknown_time
+    pub(crate) estimate_unknown: bool,
+    /// 长文本是否分段并行计算编码。结果与不分段相同，多核时更快
+    pub(crate) parallel_chunks: bool,
+    /// 生成当量表：双手互击或涉及拇指的基础当量
+    pub(crate) gen_alternate_time: f64,
+    /// 生成当量表：同手异指的基础当量
+    pub(crate) gen_same_hand_time: f64,
+    /// 生成当量表：同键连击的当量
+    pub(crate) gen_same_key_time: f64,
+    /// 生成当量表：同指异键的基础当量
+    pub(crate) gen_same_finger_time: f64,
+    /// 生成当量表：同指异键时，每键宽距离增加的当量
+    pub(crate) gen_distance_time: f64,
+    /// 生成当量表：同手异指时，每跨一排增加的当量
+    pub(crate) gen_row_jump_time: f64,
+    /// 生成当量表：后一键由各手指（小、无、中、食、拇）按下时增加的当量
+    pub(crate) gen_finger_times: [f64; 5],
+    /// 校验当量表：正反向当量相差超过此倍数时报告
+    pub(crate) check_asymmetry_ratio: f64,
+    /// 校验当量表：超出此范围的当量报告
+    pub(crate) check_time_range: (f64, f64),
+    /// 拟合当量表：超过此毫秒数的击键间隔视为停顿，不计入
+    pub(crate) fit_pause_ms: f64,
+    /// 拟合当量表：每个组合至少需要的样本数
+    pub(crate) fit_min_samples: usize,
+    /// 优化布局：参与排列的按键
+    pub(crate) opt_keys: Vec<char>,
+    /// 优化布局：固定不动的按键
+    pub(crate) opt_pinned_keys: Vec<char>,
+    /// 优化布局：随机种子
+    pub(crate) opt_seed: u64,
+    /// 优化布局：迭代次数
+    pub(crate) opt_iterations: usize,
+    /// 优化布局：初始温度，为可接受的当量相对变化
+    pub(crate) opt_temperature: f64,
+    /// 优化布局：报告中保留的最优布局数
+    pub(crate) opt_keep: usize,
+    /// 优化简码：1、2、3码简码各自的数量上限
+    pub(crate) short_slots: [usize; 3],
+    /// 优化简码：最多实际评估的候选数
+    pub(crate) short_candidates: usize,
+    /// 候选路径：保留的路径数
+    pub(crate) alt_count: usize,
+    /// 候选路径：只输出当量不超过最优路径此百分比的路径，0表示不限
+    pub(crate) alt_range: f64,
+    /// 模拟打字：前瞻的字数，0表示取最长的词组
+    pub(crate) typist_lookahead: usize,
+    /// 目标值：当量的权重
+    pub(crate) obj_time_weight: f64,
+    /// 目标值：码数的权重
+    pub(crate) obj_key_weight: f64,
+    /// 目标值：选重次数的权重
+    pub(crate) obj_selection_weight: f64,
+    /// 目标值：翻页次数的权重
+    pub(crate) obj_page_weight: f64,
+    /// 选第2、3……个候选的额外当量。候选位置超出时取最后一个值
+    pub(crate) selection_penalties: Vec<f64>,
+    /// 每次翻页的额外当量
+    pub(crate) page_penalty: f64,
+    /// 预处理：Unicode规范化形式，为“无”、“NFC”或“NFKC”
+    pub(crate) text_normalization: String,
+    /// 预处理：全半角转换，为“无”、“转半角”或“转全角”
+    pub(crate) width_folding: String,
+    /// 预处理：控制字符（含换行和制表符）的处理，为“保留”、“删除”或“转空格”
+    pub(crate) control_chars: String,
+    /// 预处理：是否把连续的空白合并为一个
+    pub(crate) collapse_whitespace: bool,
+    /// 预处理：直接删去的字符
+    pub(crate) ignore_chars: Vec<char>,
+    /// 文本中的换行所键入的按键，为空则按原字符输出
+    pub(crate) enter_keys: Vec<char>,
+    /// 文本中的空格所键入的按键，为空则按原字符输出
+    pub(crate) space_keys: Vec<char>,
+    /// 文本中的制表符所键入的按键，为空则按原字符输出
+    pub(crate) tab_keys: Vec<char>,
+    /// 是否切换到西文状态直接键入含字母的西文片段
+    pub(crate) latin_direct: bool,
+    /// 中英切换所键入的按键，在西文片段前后各按一次
+    pub(crate) ime_switch_keys: Vec<char>,
+    /// 每次中英切换额外计入的当量
+    pub(crate) ime_switch_time: f64,
+    /// 待编码文本的编码，为“自动”时自动识别
+    pub(crate) text_encoding: String,
+    /// 词库的编码，为“自动”时自动识别
+    pub(crate) dict_encoding: String,
+    /// 配置目录中的文件（设置文件本身除外）以及按键映射、权重、击键记录文件的编码，为“自动”时自动识别
+    pub(crate) config_encoding: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            modifiers: vec!['↑'],
+            modifier_time: 0.3,
+            correction_keys: vec!['←'],
+            correction_time: 2.0,
+            unknown_time: 1.5,
+            estimate_unknown: false,
+            parallel_chunks: false,
+            gen_alternate_time: 1.0,
+            gen_same_hand_time: 1.2,
+            gen_same_key_time: 1.3,
+            gen_same_finger_time: 1.6,
+            gen_distance_time: 0.2,
+            gen_row_jump_time: 0.1,
+            gen_finger_times: [0.3, 0.15, 0.0, 0.0, 0.05],
+            check_asymmetry_ratio: 1.5,
+            check_time_range: (1.0, 3.0),
+            fit_pause_ms: 1000.0,
+            fit_min_samples: 5,
+            opt_keys: "abcdefghijklmnopqrstuvwxyz".chars().collect(),
+            opt_pinned_keys: Vec::new(),
+            opt_seed: 0,
+            opt_iterations: 1000,
+            opt_temperature: 0.002,
+            opt_keep: 5,
+            short_slots: [10, 100, 0],
+            short_candidates: 500,
+            alt_count: 10,
+            alt_range: 0.0,
+            typist_lookahead: 0,
+            obj_time_weight: 1.0,
+            obj_key_weight: 0.0,
+            obj_selection_weight: 0.0,
+            obj_page_weight: 0.0,
+            selection_penalties: vec![0.0],
+            page_penalty: 0.0,
+            text_normalization: "无".to_string(),
+            width_folding: "无".to_string(),
+            control_chars: "保留".to_string(),
+            collapse_whitespace: false,
+            ignore_chars: Vec::new(),
+            enter_keys: Vec::new(),
+            space_keys: Vec::new(),
+            tab_keys: Vec::new(),
+            latin_direct: false,
+            ime_switch_keys: Vec::new(),
+            ime_switch_time: 0.0,
+            text_encoding: "自动".to_string(),
+            dict_encoding: "自动".to_string(),
+            config_encoding: "自动".to_string(),
+        }
+    }
+}
+
+impl Settings {
+    /// 按名称设置一项
+    pub(crate) fn set(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        fn parse_f64(value: &str) -> Result<f64, &'static str> {
+            value.trim().parse().map_err(|_| "无法解析为数值")
+        }
+
+        fn parse_bool(value: &str) -> Result<bool, &'static str> {
+            match value.trim() {
+                "是" | "1" | "true" => Ok(true),
+                "否" | "0" | "false" => Ok(false),
+                _ => Err("应为“是”或“否”"),
+            }
+        }
+
+        fn parse_f64_array(value: &str) -> Result<[f64; 5], &'static str> {
+            let values = value
+                .split_whitespace()
+                .map(parse_f64)
+                .collect::<Result<Vec<f64>, _>>()?;
+            values.try_into().map_err(|_| "应为以空格分隔的5个数值")
+        }
+
+        /// 按键依次写出，`空格`表示空格键
+        fn parse_keys(value: &str) -> Vec<char> {
+            match value.trim() {
+                "空格" => vec![' '],
+                v => v.chars().collect(),
+            }
+        }
+
+        /// 编码名称，如UTF-8、GBK、UTF-16LE，或“自动”
+        fn parse_encoding(value: &str) -> Result<String, &'static str> {
+            match value.trim() {
+                "自动" => Ok("自动".to_string()),
+                v if Encoding::for_label(v.as_bytes()).is_some() => Ok(v.to_string()),
+                _ => Err("未知的编码"),
+            }
+        }
+
+        fn parse_usize(value: &str) -> Result<usize, &'static str> {
+            value.trim().parse().map_err(|_| "无法解析为整数")
+        }
+
+        match name {
+            "修饰键" => self.modifiers = value.trim().chars().collect(),
+            "修饰键当量" => self.modifier_time = parse_f64(value)?,
+            "修改键" => self.correction_keys = value.trim().chars().collect(),
+            "修改键当量" => self.correction_time = parse_f64(value)?,
+            "未知当量" => self.unknown_time = parse_f64(value)?,
+            "按布局估算未知当量" => self.estimate_unknown = parse_bool(value)?,
+            "分段并行编码" => self.parallel_chunks = parse_bool(value)?,
+            "生成互击当量" => self.gen_alternate_time = parse_f64(value)?,
+            "生成同手当量" => self.gen_same_hand_time = parse_f64(value)?,
+            "生成同键当量" => self.gen_same_key_time = parse_f64(value)?,
+            "生成同指当量" => self.gen_same_finger_time = parse_f64(value)?,
+            "生成距离当量" => self.gen_distance_time = parse_f64(value)?,
+            "生成跨排当量" => self.gen_row_jump_time = parse_f64(value)?,
+            "生成手指当量" => self.gen_finger_times = parse_f64_array(value)?,
+            "拟合停顿毫秒" => self.fit_pause_ms = parse_f64(value)?,
+            "拟合最少样本" => self.fit_min_samples = parse_usize(value)?,
+            "优化按键" => self.opt_keys = value.trim().chars().collect(),
+            "优化固定按键" => self.opt_pinned_keys = value.trim().chars().collect(),
+            "优化随机种子" => {
+                self.opt_seed = value.trim().parse().map_err(|_| "无法解析为整数")?
+            }
+            "优化迭代次数" => self.opt_iterations = parse_usize(value)?,
+            "优化初始温度" => self.opt_temperature = parse_f64(value)?,
+            "优化保留数" => self.opt_keep = parse_usize(value)?,
+            "简码数量" => {
+                let values = value
+                    .split_whitespace()
+                    .map(parse_usize)
+                    .collect::<Result<Vec<usize>, _>>()?;
+                self.short_slots = values.try_into().map_err(|_| "应为以空格分隔的3个整数")?;
+            }
+            "简码候选数" => self.short_candidates = parse_usize(value)?,
+            "候选路径数" => self.alt_count = parse_usize(value)?,
+            "候选路径范围" => self.alt_range = parse_f64(value)?,
+            "打字前瞻字数" => self.typist_lookahead = parse_usize(value)?,
+            "目标当量权重" => self.obj_time_weight = parse_f64(value)?,
+            "目标码数权重" => self.obj_key_weight = parse_f64(value)?,
+            "目标选重权重" => self.obj_selection_weight = parse_f64(value)?,
+            "目标翻页权重" => self.obj_page_weight = parse_f64(value)?,
+            "选重惩罚" => {
+                let values: Vec<f64> = value
+                    .split_whitespace()
+                    .map(parse_f64)
+                    .collect::<Result<_, _>>()?;
+                if values.is_empty() {
+                    return Err("至少应有一个数值");
+                }
+                self.selection_penalties = values;
+            }
+            "翻页惩罚" => self.page_penalty = parse_f64(value)?,
+            "文本规范化" => match value.trim() {
+                v @ ("无" | "NFC" | "NFKC") => self.text_normalization = v.to_string(),
+                _ => return Err("应为“无”、“NFC”或“NFKC”"),
+            },
+            "全半角转换" => match value.trim() {
+                v @ ("无" | "转半角" | "转全角") => self.width_folding = v.to_string(),
+                _ => return Err("应为“无”、“转半角”或“转全角”"),
+            },
+            "控制字符" => match value.trim() {
+                v @ ("保留" | "删除" | "转空格") => self.control_chars = v.to_string(),
+                _ => return Err("应为“保留”、“删除”或“转空格”"),
+            },
+            "合并空白" => self.collapse_whitespace = parse_bool(value)?,
+            "忽略字符" => self.ignore_chars = value.trim().chars().collect(),
+            "换行按键" => self.enter_keys = parse_keys(value),
+            "空格按键" => self.space_keys = parse_keys(value),
+            "制表符按键" => self.tab_keys = parse_keys(value),
+            "西文直接键入" => self.latin_direct = parse_bool(value)?,
+            "中英切换按键" => self.ime_switch_keys = parse_keys(value),
+            "中英切换当量" => self.ime_switch_time = parse_f64(value)?,
+            "文本编码" => self.text_encoding = parse_encoding(value)?,
+            "词库编码" => self.dict_encoding = parse_encoding(value)?,
+            "配置编码" => self.config_encoding = parse_encoding(value)?,
+            "校验反向倍数" => self.check_asymmetry_ratio = parse_f64(value)?,
+            "校验当量范围" => {
+                let values = value
+                    .split_whitespace()
+                    .map(parse_f64)
+                    .collect::<Result<Vec<f64>, _>>()?;
+                let [min, max] = values[..] else {
+                    return Err("应为以空格分隔的下限和上限");
+                };
+                self.check_time_range = (min, max);
+            }
+            _ => return Err("未知的设置项"),
+        }
+        Ok(())
+    }
+
+    /// 选第position个候选（从2开始）的额外当量
+    pub(crate) fn selection_penalty(&self, position: usize) -> f64 {
+        let index = position
+            .saturating_sub(2)
+            .min(self.selection_penalties.len() - 1);
+        self.selection_penalties[index]
+    }
+}
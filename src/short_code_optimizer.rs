@@ -0,0 +1,152 @@
+use crate::dict_loader::{Dict, Picks, SortedItems, build_dict};
+use crate::route_buffer::RouteBuffer;
+use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+use crate::text_encoder::encode_chars;
+use std::collections::{HashMap, HashSet};
+
+/// 分配出的一个简码：(简码, 词组, 原编码, 全文当量的减少量)
+pub(crate) type ShortCode = (Vec<char>, String, Vec<char>, f64);
+
+/// 用给定的词库编码全文，返回总当量
+fn encode_total(
+    text: &[char],
+    dict: &Dict,
+    max_word_len: usize,
+    connector: &RouteConnector,
+) -> Result<f64, &'static str> {
+    let mut buffer = RouteBuffer::new(16.max(max_word_len), connector.clone())?;
+    encode_chars(text, dict, &mut buffer, false).map(|(_, time)| time)
+}
+
+/// 统计词库中各词组在文本中出现的次数，可以重叠。只用于估计简码的收益
+fn count_words(text: &[char], dict: &Dict) -> HashMap<Vec<char>, usize> {
+    let mut counts = HashMap::with_capacity(16384);
+    for i in 0..text.len() {
+        if let Some(sub_dict) = dict.get(&text[i]) {
+            for (word, _, _, _) in sub_dict {
+                if text[i..].starts_with(word) {
+                    *counts.entry(word.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// 词库中某词组的条目(词组, 编码, 当量, 选重和翻页次数)
+fn entry_mut<'a>(
+    dict: &'a mut Dict,
+    word: &[char],
+) -> Option<&'a mut (Vec<char>, Vec<char>, f64, Picks)> {
+    dict.get_mut(&word[0])?
+        .iter_mut()
+        .find(|(w, _, _, _)| w == word)
+}
+
+/// 在全码词库的基础上分配简码，使全文总当量最小。简码取自词组全码的前1~3码，且不能与词库中已有的编码重复。
+/// 先按“出现次数×当量差”估计每个候选的收益并排序，再依次实际编码全文，只保留确实减少总当量的候选，
+/// 直到各码长的简码数量用完或评估的候选数达到上限。返回(初始总当量, 最终总当量, 按分配顺序排列的简码)
+pub(crate) fn optimize(
+    text: &[char],
+    dict_items: &SortedItems,
+    punct_items: &SortedItems,
+    connector: &RouteConnector,
+    settings: &Settings,
+) -> Result<(f64, f64, Vec<ShortCode>), &'static str> {
+    let mut dict_connector = connector.clone();
+    let (mut dict, max_word_len) = build_dict(dict_items, punct_items, &mut dict_connector)?;
+    let initial = encode_total(text, &dict, max_word_len, connector)?;
+    println!("初始总当量为{initial:.1}。统计词组出现次数...");
+    let counts = count_words(text, &dict);
+
+    // 已被占用的码位
+    let chords = connector.chords();
+    let mut taken: HashSet<Vec<char>> = dict_items
+        .iter()
+        .chain(punct_items)
+        .map(|(_, code, _)| chords.parse_code(code))
+        .collect();
+
+    // 收集候选(估计收益, 词组, 简码, 简码当量)
+    let mut candidates = Vec::with_capacity(65536);
+    let mut seen = HashSet::with_capacity(65536);
+    for (word, code, _) in dict_items {
+        let word_chars: Vec<char> = word.chars().collect();
+        let Some(count) = counts.get(&word_chars) else {
+            continue; // 文本中没有出现
+        };
+        let Some((_, _, time, _)) = entry_mut(&mut dict, &word_chars) else {
+            continue;
+        };
+        let time = *time;
+        let full_code = chords.parse_code(code);
+        for len in 1..full_code.len().min(4) {
+            let short_code = full_code[..len].to_vec();
+            if settings.short_slots[len - 1] == 0
+                || taken.contains(&short_code)
+                || !seen.insert((word_chars.clone(), short_code.clone()))
+            {
+                continue;
+            }
+            let short_time = dict_connector.get_time(&short_code);
+            let gain = *count as f64 * (time - short_time);
+            if gain > 0.0 {
+                candidates.push((gain, word_chars.clone(), short_code, short_time));
+            }
+        }
+    }
+    candidates.sort_by(|(g1, w1, c1, _), (g2, w2, c2, _)| {
+        g2.partial_cmp(g1)
+            .expect("无法比较收益")
+            .then(w1.cmp(w2))
+            .then(c1.cmp(c2))
+    });
+    println!("共{}个候选。逐个评估...", candidates.len());
+
+    // 依次评估候选
+    let mut remaining = settings.short_slots;
+    let mut assigned_words = HashSet::new();
+    let mut short_codes = Vec::new();
+    let mut current = initial;
+    let mut evaluated = 0;
+    for (_, word, short_code, short_time) in candidates {
+        if remaining.iter().all(|n| *n == 0) || evaluated >= settings.short_candidates {
+            break;
+        }
+        if remaining[short_code.len() - 1] == 0
+            || taken.contains(&short_code)
+            || assigned_words.contains(&word)
+        {
+            continue;
+        }
+        evaluated += 1;
+
+        let entry = entry_mut(&mut dict, &word).ok_or("词库中找不到候选词组")?;
+        let (old_code, old_time, old_picks) = (entry.1.clone(), entry.2, entry.3);
+        (entry.1, entry.2, entry.3) = (short_code.clone(), short_time, (0, 0));
+        let time = encode_total(text, &dict, max_word_len, connector)?;
+        if time < current {
+            remaining[short_code.len() - 1] -= 1;
+            taken.insert(short_code.clone());
+            assigned_words.insert(word.clone());
+            short_codes.push((
+                short_code,
+                word.into_iter().collect(),
+                old_code,
+                current - time,
+            ));
+            current = time;
+        } else {
+            let entry = entry_mut(&mut dict, &word).ok_or("词库中找不到候选词组")?;
+            (entry.1, entry.2, entry.3) = (old_code, old_time, old_picks); // 没有收益，恢复原编码
+        }
+        print!(
+            "\r已评估{evaluated}个候选，分配{}个简码。当前总当量{current:.1}。",
+            short_codes.len()
+        );
+    }
+    println!("\n评估完成。");
+
+    Ok((initial, current, short_codes))
+}
@@ -1,37 +1,373 @@
-use crate::route_buffer::RouteBuffer;
-use std::collections::HashMap;
-use std::fs::read_to_string;
+use crate::dict_loader::Dict;
+use crate::file_decoder::decode;
+use crate::latin_typer;
+use crate::route_buffer::{RouteBuffer, Segment, align_segments};
+use crate::route_connector::RouteConnector;
+use crate::settings::Settings;
+use crate::text_preprocessor;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::read;
 use std::path::PathBuf;
 
+/// 每段至少的字数。文本不足两段时不分段
+const MIN_CHUNK_LEN: usize = 4096;
+/// 作为下一段起点的上文按键数，不少于连接器可能改动或参考的按键数
+const SEED_KEYS: usize = 8;
+/// 猜测上文末尾按键时，往前编码的字数
+const GUESS_CHARS: usize = 32;
+
+/// 编码结果
+pub(crate) struct Encoded {
+    /// 编码路径
+    pub(crate) route: Vec<char>,
+    /// 当量
+    pub(crate) time: f64,
+    /// 路径上的各段，按键起点已对齐
+    pub(crate) segments: Vec<Segment>,
+    /// 连接器，其中记录了找不到当量的按键组合
+    pub(crate) connector: RouteConnector,
+}
+
+/// 计算整篇文本的最小当量编码。设置了分段并行编码且文本较长时，在没有词组跨越的位置分段并行计算，
+/// 各段从猜测的上文末尾按键开始，猜错时重新计算，结果与不分段相同。show_progress为true时在控制台显示进度
 pub(crate) fn encode(
+    text_chars: &[char],
+    dict: &Dict,
+    connector: RouteConnector,
+    buffer_size: usize,
+    show_progress: bool,
+) -> Result<Encoded, &'static str> {
+    let latin_runs = match connector.settings().latin_direct {
+        true => latin_typer::find_runs(text_chars),
+        false => Vec::new(),
+    };
+    let boundaries = match connector.settings().parallel_chunks {
+        true => find_boundaries(text_chars, dict, buffer_size, &latin_runs),
+        false => Vec::new(),
+    };
+    if boundaries.is_empty() {
+        if show_progress {
+            println!("共需计算{}字。计算编码...", text_chars.len());
+        }
+        let mut buffer = RouteBuffer::new(buffer_size, connector)?;
+        let (route, time) = encode_chars(text_chars, dict, &mut buffer, show_progress)?;
+        let mut segments = buffer.get_segments();
+        align_segments(&mut segments);
+        if show_progress {
+            println!("\n计算完成。");
+        }
+        return Ok(Encoded {
+            route,
+            time,
+            segments,
+            connector: buffer.into_connector(),
+        });
+    }
+
+    if show_progress {
+        println!(
+            "共需计算{}字。分为{}段并行计算编码...",
+            text_chars.len(),
+            boundaries.len() + 1
+        );
+    }
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(boundaries.iter().copied())
+        .collect();
+    let ends: Vec<usize> = boundaries
+        .iter()
+        .copied()
+        .chain(std::iter::once(text_chars.len()))
+        .collect();
+
+    // 各段以猜测的上文末尾按键为起点，并行计算
+    let chunks: Vec<(usize, usize)> = starts.iter().copied().zip(ends.iter().copied()).collect();
+    let results: Vec<Result<(Vec<char>, Encoded), &'static str>> = chunks
+        .par_iter()
+        .map(|(start, end)| {
+            let seed = match start {
+                0 => Vec::new(),
+                _ => guess_seed(text_chars, dict, &connector, buffer_size, *start)?,
+            };
+            let encoded = encode_chunk(
+                &text_chars[*start..*end],
+                dict,
+                &connector,
+                buffer_size,
+                &seed,
+            )?;
+            Ok((seed, encoded))
+        })
+        .collect();
+
+    // 依次拼接。猜错的段以实际的上文末尾按键为起点重新计算
+    let mut route = Vec::with_capacity(text_chars.len() * 4);
+    let mut time = 0.0;
+    let mut segments = Vec::with_capacity(text_chars.len());
+    let mut merged_connector = connector.clone();
+    let mut rerun_count = 0;
+    for ((start, end), result) in chunks.iter().zip(results) {
+        let (mut seed, mut encoded) = result?;
+        let actual_seed = &route[route.len().saturating_sub(SEED_KEYS)..];
+        if seed != actual_seed {
+            rerun_count += 1;
+            seed = actual_seed.to_vec();
+            encoded = encode_chunk(
+                &text_chars[*start..*end],
+                dict,
+                &connector,
+                buffer_size,
+                &seed,
+            )?;
+        }
+
+        // 本段的编码以上文末尾按键开头，替换掉这部分
+        let key_offset = route.len() - seed.len();
+        route.truncate(key_offset);
+        route.extend(encoded.route);
+        let prefix_time = time;
+        time += encoded.time;
+        segments.extend(encoded.segments.into_iter().map(|mut segment| {
+            segment.start += start;
+            segment.key_start += key_offset;
+            segment.time += prefix_time;
+            segment
+        }));
+        merged_connector.merge_records(&encoded.connector);
+    }
+    align_segments(&mut segments);
+    if show_progress {
+        println!("计算完成。其中{rerun_count}段因上文不符而重新计算。");
+    }
+
+    Ok(Encoded {
+        route,
+        time,
+        segments,
+        connector: merged_connector,
+    })
+}
+
+/// 寻找分段位置：没有词组和直接键入的西文片段跨越的位置，所有路径都经过这里。
+/// 每段至少MIN_CHUNK_LEN字，按线程数均分
+fn find_boundaries(
+    text_chars: &[char],
+    dict: &Dict,
+    max_word_len: usize,
+    latin_runs: &[(usize, usize)],
+) -> Vec<usize> {
+    let chunk_len = MIN_CHUNK_LEN.max(text_chars.len() / (rayon::current_num_threads() * 4));
+    let is_safe = |p: usize| {
+        // 片段按起点升序排列
+        let i = latin_runs.partition_point(|(start, _)| *start < p);
+        let in_run = i > 0 && p < latin_runs[i - 1].1;
+        !in_run
+            && (p.saturating_sub(max_word_len)..p).all(|s| {
+                dict.get(&text_chars[s]).is_none_or(|sub_dict| {
+                    !sub_dict.iter().any(|(word, _, _, _)| {
+                        s + word.len() > p && text_chars[s..].starts_with(word)
+                    })
+                })
+            })
+    };
+
+    let mut boundaries = Vec::new();
+    let mut target = chunk_len;
+    while target + MIN_CHUNK_LEN <= text_chars.len() {
+        match (target..text_chars.len() - MIN_CHUNK_LEN).find(|p| is_safe(*p)) {
+            Some(p) => {
+                boundaries.push(p);
+                target = p + chunk_len;
+            }
+            None => break,
+        }
+    }
+    boundaries
+}
+
+/// 猜测分段位置之前的编码末尾按键：只编码分段位置之前的几个字。猜错时拼接时会重新计算
+fn guess_seed(
+    text_chars: &[char],
+    dict: &Dict,
+    connector: &RouteConnector,
+    buffer_size: usize,
+    start: usize,
+) -> Result<Vec<char>, &'static str> {
+    let window = &text_chars[start.saturating_sub(GUESS_CHARS)..start];
+    let encoded = encode_chunk(window, dict, connector, buffer_size, &[])?;
+    let route = encoded.route;
+    Ok(route[route.len().saturating_sub(SEED_KEYS)..].to_vec())
+}
+
+/// 以上文末尾按键为起点编码一段文本。返回的编码以这些按键开头，可能被连接器改动
+fn encode_chunk(
+    text_chars: &[char],
+    dict: &Dict,
+    connector: &RouteConnector,
+    buffer_size: usize,
+    seed: &[char],
+) -> Result<Encoded, &'static str> {
+    let mut buffer = RouteBuffer::new(buffer_size, connector.clone())?;
+    buffer.seed(seed);
+    let (route, time) = encode_chars(text_chars, dict, &mut buffer, false)?;
+    let segments = buffer.get_segments();
+    Ok(Encoded {
+        route,
+        time,
+        segments,
+        connector: buffer.into_connector(),
+    })
+}
+
+/// 读取并按设置预处理文本，返回文本和预处理报告的各行
+pub(crate) fn read_text(
     text_path: &PathBuf,
-    dict: HashMap<char, Vec<(Vec<char>, Vec<char>, f64)>>,
+    settings: &Settings,
+) -> Result<(Vec<char>, Vec<String>), &'static str> {
+    let bytes = read(text_path).map_err(|_| "无法读取待编码文本文件")?;
+    let text_string = decode(&bytes, &settings.text_encoding)?;
+    Ok(text_preprocessor::preprocess(&text_string, settings))
+}
+
+/// 计算一段文本的最小当量编码。show_progress为true时在控制台显示进度
+pub(crate) fn encode_chars(
+    text_chars: &[char],
+    dict: &Dict,
     buffer: &mut RouteBuffer,
+    show_progress: bool,
 ) -> Result<(Vec<char>, f64), &'static str> {
-    println!("计算编码...");
-    let text_string = read_to_string(text_path).map_err(|_| "无法读取待编码文本文件")?;
-    let text_chars: Vec<char> = text_string.chars().collect();
+    // 直接键入的西文片段。片段内部的位置只能由西文到达，词组不能止于其中
+    let pieces = latin_typer::split_pieces(
+        text_chars,
+        buffer.settings(),
+        buffer.chords(),
+        buffer.size() - 1,
+    );
+    let mut inside = vec![false; text_chars.len() + 1];
+    for (start, piece) in &pieces {
+        inside[start + 1..start + piece.len].fill(true);
+        if piece.continued {
+            inside[*start] = true;
+        }
+    }
 
-    println!("共需计算{}字。计算编码...", text_chars.len());
     for i in 0..text_chars.len() {
-        if i % 3000 == 0 {
+        if show_progress && i % 3000 == 0 {
             let count = buffer.unknown_keys_count();
             print!("\r已计算至第{i}字。遇到{}个找不到当量的按键组合。", count);
         }
-        if let Some(sub_dict) = dict.get(&text_chars[i]) {
-            for (word, code, time) in sub_dict {
-                if text_chars[i..].starts_with(word) {
-                    buffer.connect_code(word.len(), code, *time)
+        if buffer.is_reachable() {
+            if let Some(piece) = pieces.get(&i) {
+                buffer.connect_latin(piece);
+            } else if let Some(sub_dict) = dict.get(&text_chars[i]) {
+                for (word, code, time, picks) in sub_dict {
+                    if text_chars[i..].starts_with(word) && !inside[i + word.len()] {
+                        buffer.connect_code(word.len(), code, *time, Some(*picks))
+                    }
                 }
             }
-        }
-        if !buffer.is_connected() {
-            buffer.connect_code(1, &text_chars[i..i + 1], 0.0)
+            if !buffer.is_connected() {
+                buffer.connect_code(1, &text_chars[i..i + 1], 0.0, None)
+            }
         }
         buffer.next();
     }
-    let (route, time) = buffer.get_global_best_route()?;
-    println!("\n计算完成。");
+    buffer.get_global_best_route()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chord::Chords;
+    use crate::dict_loader::{SortedItems, build_dict};
+    use crate::key_map::KeyMap;
+    use crate::layout::Layout;
+    use crate::time_map::TimeMap;
+
+    /// 线性同余生成器，使测试数据固定
+    fn next(state: &mut u64) -> usize {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*state >> 33) as usize
+    }
 
-    Ok((route, time))
+    fn random_code(state: &mut u64, letters: &[char], len: usize) -> String {
+        (0..len)
+            .map(|_| letters[next(state) % letters.len()])
+            .collect()
+    }
+
+    #[test]
+    fn chunked_encode_matches_sequential() {
+        let mut state = 7;
+        let letters: Vec<char> = ('a'..='z').collect();
+        let keys: Vec<char> = letters.iter().copied().chain([' ', ',', '.']).collect();
+        let mut time_map = TimeMap::new();
+        for c1 in &keys {
+            for c2 in &keys {
+                let time = 1.0 + (next(&mut state) % 100) as f64 / 100.0;
+                time_map.pairs.insert((*c1, *c2), time);
+            }
+        }
+
+        let chars: Vec<char> = (0..300)
+            .map(|i| char::from_u32(0x4E00 + i * 7).expect("无效的字符"))
+            .collect();
+        let mut words: Vec<String> = chars.iter().map(|c| c.to_string()).collect();
+        for _ in 0..1500 {
+            let len = 2 + next(&mut state) % 3;
+            words.push((0..len).map(|_| chars[next(&mut state) % 100]).collect());
+        }
+        let dict_items: SortedItems = words
+            .iter()
+            .map(|word| {
+                let len = 1 + next(&mut state) % 4;
+                (word.clone(), random_code(&mut state, &letters, len), 0)
+            })
+            .collect();
+        let punct_items: SortedItems = vec![
+            ("，".to_string(), ",".to_string(), 0),
+            ("。".to_string(), ".".to_string(), 0),
+        ];
+
+        let mut text = Vec::new();
+        while text.len() < 3 * MIN_CHUNK_LEN {
+            match next(&mut state) % 10 {
+                0 => text.push('，'),
+                1 => text.push('。'),
+                _ => text.extend(words[next(&mut state) % words.len()].chars()),
+            }
+        }
+
+        let settings = Settings {
+            parallel_chunks: true,
+            ..Settings::default()
+        };
+        for method in 0..4 {
+            let mut connector = RouteConnector::new(
+                time_map.clone(),
+                Chords::new(&settings),
+                settings.clone(),
+                Layout::new(),
+                KeyMap::new(),
+                method,
+                None,
+            );
+            let (dict, max_word_len) =
+                build_dict(&dict_items, &punct_items, &mut connector).expect("无法生成词库");
+            let buffer_size = 16.max(max_word_len);
+            assert!(!find_boundaries(&text, &dict, buffer_size, &[]).is_empty());
+
+            let chunked =
+                encode(&text, &dict, connector.clone(), buffer_size, false).expect("无法分段编码");
+            let mut buffer = RouteBuffer::new(buffer_size, connector).expect("无法创建缓冲区");
+            let (route, time) = encode_chars(&text, &dict, &mut buffer, false).expect("无法编码");
+            assert_eq!(chunked.route, route, "连接方法{method}的编码不同");
+            assert!(
+                (chunked.time - time).abs() < 1e-6,
+                "连接方法{method}的当量不同"
+            );
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use crate::settings::Settings;
+use unicode_normalization::UnicodeNormalization;
+
+/// 全角空格
+const FULL_WIDTH_SPACE: char = '\u{3000}';
+/// 全角字符与半角ASCII字符的码位差
+const WIDTH_OFFSET: u32 = 0xFEE0;
+
+fn to_half_width(c: char) -> char {
+    match c {
+        FULL_WIDTH_SPACE => ' ',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - WIDTH_OFFSET).unwrap_or(c),
+        c => c,
+    }
+}
+
+fn to_full_width(c: char) -> char {
+    match c {
+        ' ' => FULL_WIDTH_SPACE,
+        '!'..='~' => char::from_u32(c as u32 + WIDTH_OFFSET).unwrap_or(c),
+        c => c,
+    }
+}
+
+/// 按设置预处理文本，依次为：去掉开头的BOM、Unicode规范化、全半角转换、处理控制字符、
+/// 删去忽略的字符、合并连续的空白。返回处理后的文本和报告的各行，没有改动的步骤不报告
+pub(crate) fn preprocess(raw: &str, settings: &Settings) -> (Vec<char>, Vec<String>) {
+    let mut report = Vec::new();
+    let raw = match raw.strip_prefix('\u{FEFF}') {
+        Some(rest) => {
+            report.push("删除BOM\t1".to_string());
+            rest
+        }
+        None => raw,
+    };
+
+    // 规范化可能改变字数，逐字比较没有意义，报告前后的字数
+    let mut text: Vec<char> = match settings.text_normalization.as_str() {
+        "NFC" => raw.nfc().collect(),
+        "NFKC" => raw.nfkc().collect(),
+        _ => raw.chars().collect(),
+    };
+    let before = raw.chars().count();
+    if settings.text_normalization != "无" && text.iter().copied().ne(raw.chars()) {
+        report.push(format!(
+            "{}规范化\t前后字数\t{before}\t{}",
+            settings.text_normalization,
+            text.len()
+        ));
+    }
+
+    let fold = match settings.width_folding.as_str() {
+        "转半角" => Some(to_half_width as fn(char) -> char),
+        "转全角" => Some(to_full_width as fn(char) -> char),
+        _ => None,
+    };
+    if let Some(fold) = fold {
+        let mut count = 0;
+        for c in text.iter_mut() {
+            let folded = fold(*c);
+            if folded != *c {
+                *c = folded;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            report.push(format!("{}\t{count}", settings.width_folding));
+        }
+    }
+
+    let control_count = text.iter().filter(|c| c.is_control()).count();
+    if control_count > 0 {
+        match settings.control_chars.as_str() {
+            "删除" => {
+                text.retain(|c| !c.is_control());
+                report.push(format!("删除控制字符\t{control_count}"));
+            }
+            "转空格" => {
+                text.iter_mut()
+                    .filter(|c| c.is_control())
+                    .for_each(|c| *c = ' ');
+                report.push(format!("控制字符转空格\t{control_count}"));
+            }
+            _ => {}
+        }
+    }
+
+    if !settings.ignore_chars.is_empty() {
+        let len = text.len();
+        text.retain(|c| !settings.ignore_chars.contains(c));
+        if text.len() < len {
+            report.push(format!("删除忽略的字符\t{}", len - text.len()));
+        }
+    }
+
+    // 连续的空白合并为一个：其中有换行则为换行，否则为空格
+    if settings.collapse_whitespace {
+        let len = text.len();
+        let mut collapsed = Vec::with_capacity(len);
+        let mut run: Option<bool> = None; // 正在合并的空白中是否有换行
+        for c in text {
+            if c.is_whitespace() {
+                let has_newline = run.unwrap_or(false) || c == '\n';
+                run = Some(has_newline);
+            } else {
+                if let Some(has_newline) = run.take() {
+                    collapsed.push(if has_newline { '\n' } else { ' ' });
+                }
+                collapsed.push(c);
+            }
+        }
+        if let Some(has_newline) = run {
+            collapsed.push(if has_newline { '\n' } else { ' ' });
+        }
+        text = collapsed;
+        if text.len() < len {
+            report.push(format!("合并空白减少字数\t{}", len - text.len()));
+        }
+    }
+
+    (text, report)
+}
@@ -0,0 +1,25 @@
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub(crate) struct TimeMap {
+    /// 两个按键（或并击）先后按下的用时当量：键为两个按键对应的字符，值为用时当量
+    pub(crate) pairs: HashMap<(char, char), f64>,
+    /// 并击本身的额外用时当量：键为代表并击的字符，值为用时当量
+    pub(crate) chords: HashMap<char, f64>,
+    /// 三个按键连续按下时，在两两当量之和上的修正值
+    pub(crate) triples: HashMap<(char, char, char), f64>,
+}
+
+impl TimeMap {
+    pub(crate) fn new() -> Self {
+        Self {
+            pairs: HashMap::with_capacity(4096),
+            chords: HashMap::new(),
+            triples: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pairs.len() + self.chords.len() + self.triples.len()
+    }
+}
@@ -0,0 +1,99 @@
+use crate::file_decoder::decode;
+use crate::settings::Settings;
+use std::collections::BTreeMap;
+use std::fs::read;
+use std::path::PathBuf;
+
+/// 读取击键记录文件。每行格式为`按键\t按下时刻(毫秒)`，按键为空格时可写作“空格”，用`#`号引导注释
+pub(crate) fn read_log(path: &PathBuf, encoding: &str) -> Result<Vec<(char, f64)>, &'static str> {
+    let bytes = read(path).map_err(|_| "无法读取击键记录文件")?;
+    let content = decode(&bytes, encoding)?;
+    let mut strokes = Vec::with_capacity(65536);
+    for line in content.lines() {
+        if line.is_empty() || (line.starts_with('#') && !line.starts_with("#\t")) {
+            continue;
+        }
+        let parsed = line.split_once('\t').and_then(|(key, time)| {
+            let key = match key {
+                "空格" => ' ',
+                k if k.chars().count() == 1 => k.chars().next()?,
+                _ => return None,
+            };
+            Some((key, time.trim().parse::<f64>().ok()?))
+        });
+        match parsed {
+            Some(stroke) => strokes.push(stroke),
+            None => println!("击键记录文件中有格式错误的行：{line}"),
+        }
+    }
+    Ok(strokes)
+}
+
+/// 中位数。数组须非空
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).expect("无法比较击键间隔"));
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    }
+}
+
+/// 由击键记录拟合当量表，返回与time_map.txt格式相同的各行。
+/// 超过停顿阈值或不为正的间隔视为停顿，不计入；每个组合取间隔的中位数，不受个别极端值影响；
+/// 样本不足的组合，用样本足够的反向组合代替，仍不足则不输出；最后整体缩放，使最快的组合为1.0
+pub(crate) fn fit(logs: &[Vec<(char, f64)>], settings: &Settings) -> Vec<String> {
+    // 收集每个组合的击键间隔
+    let mut intervals: BTreeMap<(char, char), Vec<f64>> = BTreeMap::new();
+    let mut pause_count = 0;
+    for strokes in logs {
+        for w in strokes.windows(2) {
+            if settings.modifiers.contains(&w[0].0) || settings.modifiers.contains(&w[1].0) {
+                continue; // 修饰键与其后的键视为并击，不单独计入
+            }
+            let interval = w[1].1 - w[0].1;
+            if interval <= 0.0 || interval > settings.fit_pause_ms {
+                pause_count += 1;
+                continue;
+            }
+            intervals
+                .entry((w[0].0, w[1].0))
+                .or_default()
+                .push(interval);
+        }
+    }
+    println!("共{}个组合，跳过{pause_count}个停顿。", intervals.len());
+
+    // 样本足够的组合取中位数
+    let mut medians: BTreeMap<(char, char), f64> = intervals
+        .iter_mut()
+        .filter(|(_, values)| values.len() >= settings.fit_min_samples)
+        .map(|(pair, values)| (*pair, median(values)))
+        .collect();
+
+    // 样本不足的组合用反向组合代替
+    let sparse: Vec<(char, char)> = intervals
+        .keys()
+        .filter(|pair| !medians.contains_key(pair))
+        .copied()
+        .collect();
+    let mut dropped_count = 0;
+    for (c1, c2) in sparse {
+        match medians.get(&(c2, c1)) {
+            Some(value) => {
+                medians.insert((c1, c2), *value);
+            }
+            None => dropped_count += 1,
+        }
+    }
+    println!(
+        "样本足够的组合{}个，样本不足而舍弃的组合{dropped_count}个。",
+        medians.len()
+    );
+
+    let fastest = medians.values().copied().fold(f64::MAX, f64::min);
+    medians
+        .into_iter()
+        .map(|((c1, c2), value)| format!("{c1}{c2}\t{:.2}", value / fastest))
+        .collect()
+}
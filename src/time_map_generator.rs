@@ -0,0 +1,68 @@
+use crate::layout::{KeyInfo, Layout};
+use crate::settings::Settings;
+use crate::time_map::TimeMap;
+
+/// 按几何模型估算先后按下两键的当量：先按手、指关系取基础当量，
+/// 同指时加上移动距离，同手时加上跨排，最后加上后一键所用手指的力度修正。
+/// 也用于编码时估算当量表中找不到的组合
+pub(crate) fn estimate(settings: &Settings, c1: char, i1: &KeyInfo, c2: char, i2: &KeyInfo) -> f64 {
+    let row_jump = match (i1.row, i2.row) {
+        (Some(r1), Some(r2)) => r1.abs_diff(r2) as f64,
+        _ => 0.0,
+    };
+    let distance = match (i1.position(), i2.position()) {
+        (Some((x1, y1)), Some((x2, y2))) => ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt(),
+        _ => row_jump,
+    };
+
+    let base = if i1.hand.is_none() || i1.hand != i2.hand || i1.is_thumb() || i2.is_thumb() {
+        settings.gen_alternate_time
+    } else if !i1.same_finger(i2) {
+        settings.gen_same_hand_time + settings.gen_row_jump_time * row_jump
+    } else if c1 == c2 {
+        settings.gen_same_key_time
+    } else {
+        settings.gen_same_finger_time + settings.gen_distance_time * distance
+    };
+    let finger_time = i2.finger.map_or(0.0, |f| settings.gen_finger_times[f]);
+    base + finger_time
+}
+
+/// 生成布局中所有按键两两组合的当量，每行格式与time_map.txt相同。
+/// 给出现有当量表时，只生成表中没有的组合，数值不做调整；否则整体缩放，使最快的组合为1.0
+pub(crate) fn generate(
+    layout: &Layout,
+    settings: &Settings,
+    existing: Option<&TimeMap>,
+) -> Vec<String> {
+    let keys: Vec<char> = layout
+        .keys()
+        .into_iter()
+        .filter(|c| !settings.modifiers.contains(c)) // 修饰键与其后的键视为并击，不单独成对
+        .collect();
+    let mut pairs = Vec::with_capacity(keys.len() * keys.len());
+    for c1 in &keys {
+        for c2 in &keys {
+            if existing.is_some_and(|time_map| time_map.pairs.contains_key(&(*c1, *c2))) {
+                continue;
+            }
+            let (i1, i2) = (
+                layout.get(*c1).expect("无法获取按键信息"),
+                layout.get(*c2).expect("无法获取按键信息"),
+            );
+            pairs.push((*c1, *c2, estimate(settings, *c1, i1, *c2, i2)));
+        }
+    }
+
+    let scale = match existing {
+        Some(_) => 1.0,
+        None => {
+            let min = pairs.iter().map(|(_, _, t)| *t).fold(f64::MAX, f64::min);
+            if min > 0.0 { 1.0 / min } else { 1.0 }
+        }
+    };
+    pairs
+        .into_iter()
+        .map(|(c1, c2, t)| format!("{c1}{c2}\t{:.2}", t * scale))
+        .collect()
+}
@@ -0,0 +1,139 @@
+use crate::chord::Chords;
+use crate::layout::Layout;
+use crate::settings::Settings;
+use crate::time_map::TimeMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// 两键组合是否在表中有当量。涉及并击时，组成并击的各键（修饰键除外）之间都有当量即可
+fn is_covered(time_map: &TimeMap, chords: &Chords, c1: char, c2: char) -> bool {
+    if time_map.pairs.contains_key(&(c1, c2)) {
+        return true;
+    }
+    let (k1, k2) = (chords.moving_keys(c1), chords.moving_keys(c2));
+    (chords.is_chord(c1) || chords.is_chord(c2))
+        && k1
+            .iter()
+            .all(|a| k2.iter().all(|b| time_map.pairs.contains_key(&(*a, *b))))
+}
+
+/// 按键在报告中的名称，空格写作“空格”
+fn key_name(c: char, chords: &Chords) -> String {
+    match c {
+        ' ' => "空格".to_string(),
+        c => chords.display(&[c]),
+    }
+}
+
+/// 校验击键当量表，返回报告的各行：布局和编码中缺少的组合、差异过大的反向组合、超出范围的当量、覆盖矩阵
+pub(crate) fn validate(
+    layout: &Layout,
+    time_map: &TimeMap,
+    chords: &Chords,
+    settings: &Settings,
+    codes: &[Vec<char>],
+) -> Vec<String> {
+    let keys: Vec<char> = layout
+        .keys()
+        .into_iter()
+        .filter(|c| !chords.is_modifier(*c)) // 修饰键与其后的键视为并击，不单独成对
+        .collect();
+    let mut report = Vec::new();
+    let display = |c1: char, c2: char| chords.display(&[c1, c2]);
+
+    // 布局中缺少的组合
+    let layout_missing: Vec<String> = keys
+        .iter()
+        .flat_map(|c1| keys.iter().map(move |c2| (*c1, *c2)))
+        .filter(|(c1, c2)| !time_map.pairs.contains_key(&(*c1, *c2)))
+        .map(|(c1, c2)| display(c1, c2))
+        .collect();
+    report.push(format!(
+        "---布局中的{}键两两组合，缺少{}个---",
+        keys.len(),
+        layout_missing.len()
+    ));
+    report.extend(layout_missing);
+
+    // 编码中缺少的组合，按出现次数降序
+    let mut code_missing: BTreeMap<(char, char), usize> = BTreeMap::new();
+    for code in codes {
+        for w in code.windows(2) {
+            if !is_covered(time_map, chords, w[0], w[1])
+                && !settings.correction_keys.contains(&w[0])
+                && !settings.correction_keys.contains(&w[1])
+            {
+                *code_missing.entry((w[0], w[1])).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut code_missing: Vec<_> = code_missing.into_iter().collect();
+    code_missing.sort_by(|(_, n1), (_, n2)| n2.cmp(n1));
+    report.push(format!(
+        "---{}条编码中，缺少{}个组合（组合\t出现次数）---",
+        codes.len(),
+        code_missing.len()
+    ));
+    report.extend(
+        code_missing
+            .iter()
+            .map(|((c1, c2), n)| format!("{}\t{n}", display(*c1, *c2))),
+    );
+
+    // 差异过大的反向组合
+    let mut asymmetric = BTreeSet::new();
+    for ((c1, c2), t1) in &time_map.pairs {
+        if let Some(t2) = time_map.pairs.get(&(*c2, *c1))
+            && c1 < c2
+            && t1.max(*t2) > t1.min(*t2) * settings.check_asymmetry_ratio
+        {
+            asymmetric.insert(format!(
+                "{}\t{t1}\t{}\t{t2}",
+                display(*c1, *c2),
+                display(*c2, *c1)
+            ));
+        }
+    }
+    report.push(format!(
+        "---正反向当量相差{}倍以上的组合，共{}对---",
+        settings.check_asymmetry_ratio,
+        asymmetric.len()
+    ));
+    report.extend(asymmetric);
+
+    // 超出范围的当量
+    let (min, max) = settings.check_time_range;
+    let mut out_of_range: Vec<String> = time_map
+        .pairs
+        .iter()
+        .filter(|(_, t)| **t < min || **t > max)
+        .map(|((c1, c2), t)| format!("{}\t{t}", display(*c1, *c2)))
+        .collect();
+    out_of_range.sort();
+    report.push(format!(
+        "---超出{min}~{max}的当量，共{}个---",
+        out_of_range.len()
+    ));
+    report.extend(out_of_range);
+
+    // 覆盖矩阵：行为前一键，列为后一键，■为有当量，·为缺少；行末为该键作为前一键的覆盖数
+    report.push("---覆盖矩阵（行为前一键，列为后一键，■为有当量，·为缺少）---".to_string());
+    let header: String = keys.iter().map(|c| chords.display(&[*c])).collect();
+    report.push(format!("\t{header}\t覆盖数"));
+    for c1 in &keys {
+        let row: String = keys
+            .iter()
+            .map(|c2| match time_map.pairs.contains_key(&(*c1, *c2)) {
+                true => '■',
+                false => '·',
+            })
+            .collect();
+        let count = row.chars().filter(|c| *c == '■').count();
+        report.push(format!(
+            "{}\t{row}\t{count}/{}",
+            key_name(*c1, chords),
+            keys.len()
+        ));
+    }
+
+    report
+}
@@ -0,0 +1,124 @@
+use crate::dict_loader::Dict;
+use crate::route_connector::RouteConnector;
+use std::collections::HashMap;
+
+/// 连接时传给连接器的上文按键数。连接器只改动上文的末尾，不必传入整条路径
+const TAIL_KEYS: usize = 8;
+
+/// 一个词组的(字数, 编码, 当量)
+type Word<'a> = (usize, &'a [char], f64);
+
+/// 从某位置开始的词组(字数, 编码, 当量)。没有则直接输出原字符
+fn words_at<'a>(text: &'a [char], dict: &'a Dict, i: usize) -> Vec<Word<'a>> {
+    let mut words: Vec<Word> = match dict.get(&text[i]) {
+        Some(sub_dict) => sub_dict
+            .iter()
+            .filter(|(word, _, _, _)| text[i..].starts_with(word))
+            .map(|(word, code, time, _)| (word.len(), &code[..], *time))
+            .collect(),
+        None => Vec::new(),
+    };
+    if words.is_empty() {
+        words.push((1, &text[i..i + 1], 0.0));
+    }
+    words
+}
+
+/// 把编码接到路径末尾，返回新路径和新当量
+fn append(
+    connector: &mut RouteConnector,
+    route: &[char],
+    time: f64,
+    code: &[char],
+    code_time: f64,
+) -> (Vec<char>, f64) {
+    let cut = route.len().saturating_sub(TAIL_KEYS);
+    let (joined, new_time) = connector.connect(&route[cut..], code, time, code_time);
+    let mut new_route = route[..cut].to_vec();
+    new_route.extend(joined);
+    (new_route, new_time)
+}
+
+/// 在当前位置选一个词组：前瞻字数为0时取最长的词组（同长取当量小的）；
+/// 否则在接下来的前瞻字数内试遍各种分词，取每字当量最小的一种，只打出其中的第一个词组。
+/// 返回所选词组的(字数, 编码, 当量)
+fn choose<'a>(
+    text: &'a [char],
+    dict: &'a Dict,
+    connector: &mut RouteConnector,
+    route: &[char],
+    time: f64,
+    i: usize,
+    lookahead: usize,
+) -> Word<'a> {
+    let words = words_at(text, dict, i);
+    if lookahead == 0 {
+        return *words
+            .iter()
+            .max_by(|(l1, _, t1), (l2, _, t2)| {
+                l1.cmp(l2).then(t2.partial_cmp(t1).expect("无法比较当量"))
+            })
+            .expect("无法选择词组");
+    }
+
+    // 窗口内逐位置只保留最优的(上文末尾, 当量, 第一个词组)
+    let tail = &route[route.len().saturating_sub(TAIL_KEYS)..];
+    let end = text.len().min(i + lookahead);
+    let mut states: HashMap<usize, (Vec<char>, f64, Word)> = HashMap::new();
+    let mut best: Option<(f64, Word)> = None;
+    let mut consider = |states: &mut HashMap<_, _>, pos: usize, keys: Vec<char>, t: f64, first| {
+        if pos >= end {
+            // 越过窗口：按每字当量比较
+            let per_char = (t - time) / (pos - i) as f64;
+            if best.is_none_or(|(b, _)| per_char < b) {
+                best = Some((per_char, first));
+            }
+        } else if states
+            .get(&pos)
+            .is_none_or(|(_, old, _): &(Vec<char>, f64, _)| t < *old)
+        {
+            states.insert(pos, (keys, t, first));
+        }
+    };
+    for word in &words {
+        let (keys, t) = append(connector, tail, time, word.1, word.2);
+        consider(&mut states, i + word.0, keys, t, *word);
+    }
+    for pos in i + 1..end {
+        let Some((keys, t, first)) = states.get(&pos).cloned() else {
+            continue;
+        };
+        for (len, code, code_time) in words_at(text, dict, pos) {
+            let (new_keys, new_t) = append(connector, &keys, t, code, code_time);
+            consider(&mut states, pos + len, new_keys, new_t, first);
+        }
+    }
+    best.expect("无法选择词组").1
+}
+
+/// 模拟实际的打字者：逐词决定，打出后不再修改。返回(编码路径, 总当量)
+pub(crate) fn simulate(
+    text: &[char],
+    dict: &Dict,
+    connector: &mut RouteConnector,
+    lookahead: usize,
+) -> (Vec<char>, f64) {
+    let mut route = Vec::with_capacity(text.len() * 4);
+    let mut time = 0.0;
+    let (mut i, mut next_report) = (0, 0);
+    while i < text.len() {
+        if i >= next_report {
+            print!("\r已模拟至第{i}字。");
+            next_report += 3000;
+        }
+        let (len, code, code_time) = choose(text, dict, connector, &route, time, i, lookahead);
+        let cut = route.len().saturating_sub(TAIL_KEYS);
+        let (joined, new_time) = connector.connect(&route[cut..], code, time, code_time);
+        route.truncate(cut);
+        route.extend(joined);
+        time = new_time;
+        i += len;
+    }
+    println!("\n模拟完成。");
+    (route, time)
+}
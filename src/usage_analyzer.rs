@@ -0,0 +1,74 @@
+use crate::chord::Chords;
+use crate::dict_loader::Dict;
+use crate::route_buffer::Segment;
+use std::collections::{BTreeMap, HashMap};
+
+/// 统计最优路径用到的词库条目，返回报告的各行。
+/// 依次为概况、用到的条目（按次数降序）、没用到的条目、词库中没有而直接输出的字符
+pub(crate) fn analyze(
+    text: &[char],
+    dict: &Dict,
+    segments: &[Segment],
+    chords: &Chords,
+) -> Vec<String> {
+    let mut used: HashMap<(&[char], &[char]), usize> = HashMap::with_capacity(16384);
+    let mut raw_chars: BTreeMap<char, usize> = BTreeMap::new();
+    let mut latin_chars = 0;
+    for segment in segments {
+        let word = &text[segment.start..segment.start + segment.len];
+        match (segment.in_dict, segment.latin) {
+            (true, _) => *used.entry((word, &segment.code)).or_insert(0) += 1,
+            (false, true) => latin_chars += segment.len,
+            (false, false) => *raw_chars.entry(word[0]).or_insert(0) += 1,
+        }
+    }
+
+    // 词库中的每个条目，附上使用次数
+    let mut entries: Vec<(&[char], &[char], f64, usize)> = dict
+        .values()
+        .flatten()
+        .map(|(word, code, time, _)| {
+            let count = used.get(&(&word[..], &code[..])).copied().unwrap_or(0);
+            (&word[..], &code[..], *time, count)
+        })
+        .collect();
+    entries
+        .sort_by(|(w1, c1, _, n1), (w2, c2, _, n2)| n2.cmp(n1).then(w1.cmp(w2)).then(c1.cmp(c2)));
+    let used_count = entries.iter().filter(|(_, _, _, n)| *n > 0).count();
+
+    let mut report = vec![
+        format!("词条总数\t{}", entries.len()),
+        format!("用到的词条\t{used_count}"),
+        format!("没用到的词条\t{}", entries.len() - used_count),
+        format!(
+            "词库中没有的字符\t{}\t共出现\t{}次",
+            raw_chars.len(),
+            raw_chars.values().sum::<usize>()
+        ),
+        format!("直接键入的西文\t{latin_chars}字"),
+        "---以下为用到的词条：词组、编码、次数、当量---".to_string(),
+    ];
+    let format_entry = |(word, code, time, count): &(&[char], &[char], f64, usize)| {
+        let word: String = word
+            .iter()
+            .map(|c| match c {
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                c => c.to_string(),
+            })
+            .collect();
+        format!("{word}\t{}\t{count}\t{time:.2}", chords.display(code))
+    };
+    report.extend(entries[..used_count].iter().map(format_entry));
+    report.push("---以下为没用到的词条：词组、编码、次数、当量---".to_string());
+    report.extend(entries[used_count..].iter().map(format_entry));
+    report.push("---以下为词库中没有的字符：字符、次数---".to_string());
+    report.extend(raw_chars.iter().map(|(c, count)| match c {
+        '\n' => format!("换行\t{count}"),
+        '\t' => format!("制表符\t{count}"),
+        ' ' => format!("空格\t{count}"),
+        c => format!("{c}\t{count}"),
+    }));
+    report
+}